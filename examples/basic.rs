@@ -30,7 +30,7 @@ fn main() {
         poll_n_times(2).await;
         println!("async bye 2");
 
-        println!("{}", task.await);
+        println!("{}", task.await.unwrap());
     });
     println!("bye");
 }
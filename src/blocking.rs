@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::util::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+const DEFAULT_MAX_THREADS: usize = 4;
+
+// Idle threads exit after this duration without work.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Pool {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle_threads: usize,
+    max_threads: usize,
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    jobs: VecDeque::new(),
+    threads: 0,
+    idle_threads: 0,
+    max_threads: DEFAULT_MAX_THREADS,
+});
+static JOB_AVAILABLE: Condvar = Condvar::new();
+
+fn submit(job: Job) -> io::Result<()> {
+    let mut pool = POOL.lock().unwrap();
+    if pool.idle_threads > 0 || pool.threads >= pool.max_threads {
+        // Queued until a thread finishes its current job, or picked up by an
+        // idle thread right away.
+        pool.jobs.push_back(job);
+        JOB_AVAILABLE.notify_one();
+        return Ok(());
+    }
+
+    // Spawn without holding the lock, the new thread runs the job first.
+    pool.threads += 1;
+    drop(pool);
+    let spawned = thread::Builder::new()
+        .name("winmsg-executor-blocking".to_string())
+        .spawn(move || worker(job));
+    if let Err(err) = spawned {
+        POOL.lock().unwrap().threads -= 1;
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn worker(first_job: Job) {
+    // Panics are caught by the job itself.
+    first_job();
+    let mut pool = POOL.lock().unwrap();
+    loop {
+        if let Some(job) = pool.jobs.pop_front() {
+            drop(pool);
+            // Panics are caught by the job itself.
+            job();
+            pool = POOL.lock().unwrap();
+            continue;
+        }
+
+        // Shrink the pool after the size was lowered.
+        if pool.threads > pool.max_threads {
+            break;
+        }
+
+        pool.idle_threads += 1;
+        let (guard, timeout) = JOB_AVAILABLE.wait_timeout(pool, IDLE_TIMEOUT).unwrap();
+        pool = guard;
+        pool.idle_threads -= 1;
+        if timeout.timed_out() && pool.jobs.is_empty() {
+            break;
+        }
+    }
+    pool.threads -= 1;
+}
+
+/// Sets the maximum number of threads running [`spawn_blocking()`] closures,
+/// defaults to 4.
+///
+/// Applies to all threads of the process. Values smaller than 1 are treated
+/// as 1. When lowered, surplus threads exit once their current closure
+/// returns.
+pub fn set_blocking_threads(max_threads: usize) {
+    POOL.lock().unwrap().max_threads = max_threads.max(1);
+}
+
+/// Runs a blocking closure on a thread pool and returns a future for its
+/// result.
+///
+/// Use it for blocking APIs (synchronous file I/O, registry scans, ...) which
+/// would otherwise stall the message loop. The returned future resolves on
+/// the thread awaiting it, woken with a message posted to its message loop.
+///
+/// The closure starts running right away, without being polled. Threads are
+/// started on demand up to the limit set with [`set_blocking_threads()`],
+/// further closures are queued. Dropping the future does not cancel the
+/// closure, its result is discarded. A panic of the closure is resumed when
+/// the future is polled.
+///
+/// # Panics
+///
+/// Panics when a thread of the pool cannot be started, see
+/// [`try_spawn_blocking()`].
+pub fn spawn_blocking<T, F>(f: F) -> impl Future<Output = T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    try_spawn_blocking(f).expect("failed to spawn blocking pool thread")
+}
+
+/// Same as [`spawn_blocking()`] but fails instead of panicking when a thread
+/// of the pool cannot be started.
+///
+/// The closure is dropped without running in that case.
+pub fn try_spawn_blocking<T, F>(f: F) -> io::Result<impl Future<Output = T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = oneshot();
+    submit(Box::new(move || {
+        // The receiver might be gone already, ignore the error.
+        let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+    }))?;
+    Ok(async move {
+        match receiver
+            .await
+            .expect("blocking job always sends its result")
+        {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn runs_on_pool_thread() {
+        let current = thread::current().id();
+        block_on(async {
+            let jobs: Vec<_> = (0..8)
+                .map(|i| spawn_blocking(move || (i, thread::current().id())))
+                .collect();
+            for (i, job) in jobs.into_iter().enumerate() {
+                let (j, thread_id) = job.await;
+                assert_eq!(i, j);
+                assert_ne!(thread_id, current);
+            }
+            // Resolves on the awaiting thread.
+            assert_eq!(thread::current().id(), current);
+        });
+    }
+
+    #[test]
+    fn try_spawn() {
+        let job = try_spawn_blocking(|| 42).unwrap();
+        assert_eq!(block_on(job), 42);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_resumed() {
+        block_on(spawn_blocking(|| panic!()));
+    }
+}
@@ -0,0 +1,353 @@
+//! Minimal set of future combinators, for users who do not want to depend on
+//! the `futures` crate.
+//!
+//! All combinators drive their futures from within the calling task, nothing
+//! is spawned. The futures do not need to be `Send`.
+
+use std::{
+    cell::RefCell,
+    future::{poll_fn, Future},
+    mem,
+    pin::{pin, Pin},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+/// Output of [`race()`], tells which of the two futures completed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// The first future completed first.
+    Left(A),
+
+    /// The second future completed first.
+    Right(B),
+}
+
+/// Runs two futures concurrently and returns the output of the one that
+/// completes first.
+///
+/// The other future is dropped right away, releasing its resources (timers,
+/// handle waits, ...). When both futures are ready at the same time, `a` wins
+/// because it is always polled first.
+pub async fn race<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = a.as_mut().poll(cx) {
+            Poll::Ready(Either::Left(output))
+        } else {
+            b.as_mut().poll(cx).map(Either::Right)
+        }
+    })
+    .await
+}
+
+/// Runs all futures concurrently and returns the index and output of the one
+/// that completes first.
+///
+/// All other futures are dropped right away. When multiple futures are ready
+/// at the same time, the one with the lowest index wins.
+///
+/// # Panics
+///
+/// Panics when polled with an empty `futures` vector, which would never
+/// complete otherwise.
+pub async fn race_all<F: Future>(futures: Vec<F>) -> (usize, F::Output) {
+    assert!(
+        !futures.is_empty(),
+        "race_all() requires at least one future"
+    );
+    let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    poll_fn(|cx| {
+        for (i, future) in futures.iter_mut().enumerate() {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready((i, output));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Runs two futures concurrently and returns both outputs once both have
+/// completed.
+pub async fn join2<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    let mut a_output = None;
+    let mut b_output = None;
+    poll_fn(|cx| {
+        // Completed futures must not be polled again.
+        if a_output.is_none() {
+            a_output = ready(a.as_mut().poll(cx));
+        }
+        if b_output.is_none() {
+            b_output = ready(b.as_mut().poll(cx));
+        }
+        if a_output.is_some() && b_output.is_some() {
+            Poll::Ready((a_output.take().unwrap(), b_output.take().unwrap()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Runs all futures concurrently and returns their outputs once all have
+/// completed.
+///
+/// The outputs are in the same order as the input futures.
+pub async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<_> = futures.iter().map(|_| None).collect();
+    poll_fn(|cx| {
+        let mut all_ready = true;
+        for (future, output) in futures.iter_mut().zip(&mut outputs) {
+            if output.is_none() {
+                *output = ready(future.as_mut().poll(cx));
+                all_ready &= output.is_some();
+            }
+        }
+        if all_ready {
+            Poll::Ready(outputs.drain(..).map(Option::unwrap).collect())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+type ScopedFuture<'env> = Pin<Box<dyn Future<Output = ()> + 'env>>;
+
+/// Handle to spawn child futures into a [`scope()`].
+///
+/// Can be cloned to spawn from within child futures.
+#[derive(Clone)]
+pub struct Scope<'env> {
+    // Spawned but not yet polled by the scope.
+    spawned: Rc<RefCell<Vec<ScopedFuture<'env>>>>,
+}
+
+impl<'env> Scope<'env> {
+    /// Spawns a child future, which may borrow from outside of the scope.
+    ///
+    /// The child runs concurrently with the scope body and the other children.
+    /// Futures spawned after the scope completed are never polled.
+    pub fn spawn<T: 'env>(&self, future: impl Future<Output = T> + 'env) -> ScopedJoinHandle<T> {
+        let state = Rc::new(RefCell::new(ScopedJoinState {
+            output: None,
+            waker: None,
+        }));
+        let handle = ScopedJoinHandle {
+            state: state.clone(),
+        };
+        self.spawned.borrow_mut().push(Box::pin(async move {
+            let output = future.await;
+            let waker = {
+                let mut state = state.borrow_mut();
+                state.output = Some(output);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }));
+        handle
+    }
+}
+
+struct ScopedJoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Awaits the output of a child future spawned with [`Scope::spawn`].
+///
+/// Dropping the handle does not stop the child, the scope still waits for it.
+pub struct ScopedJoinHandle<T> {
+    state: Rc<RefCell<ScopedJoinState<T>>>,
+}
+
+impl<T> Future for ScopedJoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+        if let Some(output) = state.output.take() {
+            return Poll::Ready(output);
+        }
+        match &mut state.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            waker => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+/// Runs the future returned by `f` and all child futures spawned with the
+/// [`Scope`] passed to it, returns the output of `f` once all completed.
+///
+/// Unlike tasks spawned with [`spawn_local()`](crate::spawn_local), children
+/// do not need to be `'static`: They may borrow local variables of the
+/// calling task. This is sound because the children are owned and polled by
+/// the scope future itself. When it is dropped before completion, all
+/// children are dropped right away, aborting them. A panic of a child
+/// unwinds out of the scope.
+///
+/// ```
+/// use winmsg_executor::combinator::scope;
+///
+/// let mut counts = [0, 0];
+/// let counts_ref = &mut counts;
+/// winmsg_executor::block_on(scope(|s| async move {
+///     for count in counts_ref {
+///         s.spawn(async move { *count += 1 });
+///     }
+/// }));
+/// assert_eq!(counts, [1, 1]);
+/// ```
+pub async fn scope<'env, F, R>(f: impl FnOnce(Scope<'env>) -> F) -> R
+where
+    F: Future<Output = R> + 'env,
+{
+    let scope = Scope {
+        spawned: Rc::default(),
+    };
+    let mut body = pin!(f(scope.clone()));
+    let mut body_output = None;
+    let mut children: Vec<ScopedFuture<'env>> = Vec::new();
+    poll_fn(|cx| {
+        if body_output.is_none() {
+            body_output = ready(body.as_mut().poll(cx));
+        }
+        children.retain_mut(|child| child.as_mut().poll(cx).is_pending());
+        // Children spawned while polling are polled right away.
+        loop {
+            let mut spawned = mem::take(&mut *scope.spawned.borrow_mut());
+            if spawned.is_empty() {
+                break;
+            }
+            spawned.retain_mut(|child| child.as_mut().poll(cx).is_pending());
+            children.append(&mut spawned);
+        }
+        if children.is_empty() && body_output.is_some() {
+            Poll::Ready(body_output.take().unwrap())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+fn ready<T>(poll: Poll<T>) -> Option<T> {
+    match poll {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, time::Duration};
+
+    use super::*;
+    use crate::{block_on, timer::sleep, yield_now};
+
+    async fn poll_n_times<T>(n: usize, output: T) -> T {
+        for _ in 1..n {
+            yield_now().await;
+        }
+        output
+    }
+
+    #[test]
+    fn race_first_wins() {
+        block_on(async {
+            assert_eq!(
+                race(sleep(Duration::from_secs(60)), poll_n_times(3, 42)).await,
+                Either::Right(42)
+            );
+            assert_eq!(race(async { 1 }, async { 2 }).await, Either::Left(1));
+        });
+    }
+
+    #[test]
+    fn race_all_first_wins() {
+        block_on(async {
+            let futures = vec![
+                poll_n_times(3, 'a'),
+                poll_n_times(2, 'b'),
+                poll_n_times(2, 'c'),
+            ];
+            assert_eq!(race_all(futures).await, (1, 'b'));
+        });
+    }
+
+    #[test]
+    fn join_all_outputs() {
+        block_on(async {
+            assert_eq!(
+                join2(poll_n_times(3, 'a'), poll_n_times(1, 'b')).await,
+                ('a', 'b')
+            );
+
+            let futures = (0..5).map(|i| poll_n_times(5 - i, i)).collect();
+            assert_eq!(join_all(futures).await, [0, 1, 2, 3, 4]);
+            assert!(join_all(Vec::<std::future::Ready<()>>::new())
+                .await
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn scope_borrows() {
+        let log = RefCell::new(Vec::new());
+        let log_ref = &log;
+        let output = block_on(scope(|s| async move {
+            let child = s.spawn({
+                let s = s.clone();
+                async move {
+                    poll_n_times(3, ()).await;
+                    log_ref.borrow_mut().push("child");
+                    // Spawn from within a child.
+                    s.spawn(async move { log_ref.borrow_mut().push("grandchild") });
+                    1
+                }
+            });
+            s.spawn(async move { log_ref.borrow_mut().push("detached") });
+            child.await + 1
+        }));
+        assert_eq!(output, 2);
+        assert_eq!(*log.borrow(), ["detached", "child", "grandchild"]);
+    }
+
+    #[test]
+    fn scope_dropped() {
+        struct SetOnDrop<'a>(&'a Cell<bool>);
+
+        impl Drop for SetOnDrop<'_> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let dropped = Cell::new(false);
+        let dropped_ref = &dropped;
+        block_on(async {
+            let scope = scope(|s| async move {
+                s.spawn(async move {
+                    let _guard = SetOnDrop(dropped_ref);
+                    sleep(Duration::from_secs(60)).await;
+                });
+            });
+            race(scope, yield_now()).await;
+        });
+        assert!(dropped.get());
+    }
+
+    #[test]
+    #[should_panic]
+    fn race_all_empty() {
+        block_on(race_all(Vec::<std::future::Ready<()>>::new()));
+    }
+}
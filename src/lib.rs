@@ -1,86 +1,661 @@
 #![doc = include_str!("../README.md")]
 
+pub mod combinator;
+pub mod task_local;
+pub mod timer;
 pub mod util;
 
+mod blocking;
+pub use blocking::{set_blocking_threads, spawn_blocking, try_spawn_blocking};
+
 use std::{
     any::Any,
-    cell::Cell,
-    future::Future,
-    mem::{ManuallyDrop, MaybeUninit},
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt,
+    future::{poll_fn, Future},
+    io,
+    mem::MaybeUninit,
+    ops::ControlFlow,
     panic::{self, AssertUnwindSafe},
     pin::{pin, Pin},
     ptr::{self, NonNull},
-    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
 };
 
 use async_task::Runnable;
-use util::{Window, WindowType};
-use windows_sys::Win32::UI::WindowsAndMessaging::*;
+#[cfg(feature = "futures-spawn")]
+use futures_task::{FutureObj, LocalFutureObj, SpawnError};
+use util::{oneshot, Window, WindowCreationError, WindowType};
+#[cfg(feature = "apc-wake")]
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, FALSE, HANDLE, TRUE},
+    System::Threading::{
+        GetCurrentThreadId, OpenThread, QueueUserAPC, SleepEx, THREAD_SET_CONTEXT,
+    },
+};
+#[cfg(not(feature = "apc-wake"))]
+use windows_sys::Win32::{
+    Foundation::{GetLastError, ERROR_INVALID_WINDOW_HANDLE},
+    System::Threading::GetCurrentThreadId,
+};
+use windows_sys::{
+    core::PCWSTR,
+    w,
+    Win32::{
+        Foundation::HWND,
+        System::{SystemInformation::GetTickCount, Threading::INFINITE},
+        UI::WindowsAndMessaging::*,
+    },
+};
+
+use crate::{task_local::TaskLocals, util::MsgFilterHook};
+
+// Registered instead of using `WM_USER` based IDs, which are often reused by
+// other code, e.g. when the window gets subclassed.
+fn msg_id_wake() -> u32 {
+    static ID: OnceLock<u32> = OnceLock::new();
+    *ID.get_or_init(|| register_message(w!("winmsg-executor-wake")))
+}
+
+fn msg_id_spawn() -> u32 {
+    static ID: OnceLock<u32> = OnceLock::new();
+    *ID.get_or_init(|| register_message(w!("winmsg-executor-spawn")))
+}
+
+// Smallest range of message ids that contains the wake and spawn messages.
+// Other messages in the range are for the executor window too, they are
+// ignored by its window procedure.
+fn executor_msg_range() -> (u32, u32) {
+    (
+        msg_id_wake().min(msg_id_spawn()),
+        msg_id_wake().max(msg_id_spawn()),
+    )
+}
+
+fn register_message(name: PCWSTR) -> u32 {
+    let id = unsafe { RegisterWindowMessageW(name) };
+    assert_ne!(id, 0, "cannot register window message");
+    id
+}
+
+// Passed as `wparam` of the wake and spawn messages. Their `lparam` is only
+// interpreted as pointer when the cookie matches, so that messages with the
+// same ID sent by other code (e.g. a broadcast) are ignored.
+const MSG_COOKIE: usize = 0x7769_6e6d_7367_6578_u64 as usize;
+
+// Maximum number of input and paint messages dispatched in one go by
+// `yield_now()` or the poll budget. A window that does not validate its
+// update region receives `WM_PAINT` over and over, the tasks would starve.
+const UI_DRAIN_LIMIT: usize = 64;
+
+// Double boxed to pass it as thin pointer in the `lparam` of a message.
+type SpawnFn = Box<dyn FnOnce() + Send>;
+
+type PanicPayload = Box<dyn Any + Send + 'static>;
+
+// Per task data stored alongside the future by `async-task`. Must be `Sync`
+// for the `JoinHandle` to be `Send`, thread confined data like the task
+// locals lives in the future instead.
+struct TaskMetadata {
+    // Propagate panics to the message loop instead of the `JoinHandle`.
+    // Set when nobody is awaiting the task anymore to observe a panic.
+    propagate_panic: AtomicBool,
+    id: TaskId,
+}
+
+type Task<T> = async_task::Task<Result<T, PanicPayload>, TaskMetadata>;
 
-use crate::util::MsgFilterHook;
+// Handle of an executor thread to queue wake APCs to. Shared by its tasks so
+// that it stays valid for as long as a task can be woken.
+#[cfg(feature = "apc-wake")]
+struct ThreadHandle(HANDLE);
 
-const MSG_ID_WAKE: u32 = WM_USER;
+// SAFETY: Thread handles can be used from any thread.
+#[cfg(feature = "apc-wake")]
+unsafe impl Send for ThreadHandle {}
+#[cfg(feature = "apc-wake")]
+unsafe impl Sync for ThreadHandle {}
+
+#[cfg(feature = "apc-wake")]
+impl ThreadHandle {
+    fn current() -> Self {
+        Self(unsafe { OpenThread(THREAD_SET_CONTEXT, FALSE, GetCurrentThreadId()) })
+    }
+}
+
+#[cfg(feature = "apc-wake")]
+impl Drop for ThreadHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+// Wait flags of the message loops.
+#[cfg(feature = "apc-wake")]
+const MWMO_WAKE: MSG_WAIT_FOR_MULTIPLE_OBJECTS_EX_FLAGS = MWMO_ALERTABLE;
+#[cfg(not(feature = "apc-wake"))]
+const MWMO_WAKE: MSG_WAIT_FOR_MULTIPLE_OBJECTS_EX_FLAGS = 0;
 
 thread_local! {
-    static PANIC_PAYLOAD: Cell<Option<Box<dyn Any + Send + 'static>>> = const { Cell::new(None) };
-    static EXECUTOR_WINDOW: Window<()> = Window::new(WindowType::MessageOnly, (), |_, msg| {
-        if msg.msg == MSG_ID_WAKE {
-            let runnable = unsafe {
-                let runnable_ptr = NonNull::new_unchecked(msg.lparam as *mut _);
-                Runnable::<()>::from_raw(runnable_ptr)
-            };
-            if let Err(panic_payload) = panic::catch_unwind(|| runnable.run()) {
+    #[cfg(feature = "task-count")]
+    static TASK_COUNT: Cell<usize> = const { Cell::new(0) };
+    // Woken whenever a task is dropped, used by `shutdown()`.
+    #[cfg(feature = "task-count")]
+    static TASK_COUNT_WAKER: Cell<Option<Waker>> = const { Cell::new(None) };
+    // Number of tasks being polled right now, more than one for nested loops.
+    #[cfg(feature = "task-count")]
+    static POLL_DEPTH: Cell<usize> = const { Cell::new(0) };
+    #[cfg(feature = "task-count")]
+    static SHUTTING_DOWN: Cell<bool> = const { Cell::new(false) };
+    static PANIC_PAYLOAD: Cell<Option<PanicPayload>> = const { Cell::new(None) };
+    static WAKE_BATCH_SIZE: Cell<usize> = const { Cell::new(1) };
+    static NEXT_TASK_ID: Cell<u64> = const { Cell::new(0) };
+    // Number of message loops (`block_on()` and `MessageLoop::run()`) on the
+    // call stack.
+    static LOOP_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // Instrumentation hooks installed by `MessageLoopConfig::run()`.
+    static MESSAGE_HOOK: Cell<Option<fn(&MSG)>> = const { Cell::new(None) };
+    static POLL_HOOK: Cell<Option<fn(TaskId)>> = const { Cell::new(None) };
+    // Poll budget installed by `MessageLoopConfig::run()`, 0 when unlimited.
+    static POLL_BUDGET: Cell<usize> = const { Cell::new(0) };
+    static POLLS_SINCE_UI: Cell<usize> = const { Cell::new(0) };
+    // Set by `yield_now()` to service input and paint messages after the
+    // current message.
+    static UI_YIELD: Cell<bool> = const { Cell::new(false) };
+    #[cfg(feature = "apc-wake")]
+    static WAKE_THREAD: Arc<ThreadHandle> = Arc::new(ThreadHandle::current());
+    // Fails when the thread reached the window handle limit, reported by
+    // `try_spawn_local()`.
+    static EXECUTOR_WINDOW: RefCell<Result<Window<()>, WindowCreationError>> = RefCell::new(Window::new(WindowType::MessageOnly, (), |_, msg| {
+        if msg.msg == WM_DESTROY {
+            drop_queued_messages(msg.hwnd);
+            None
+        } else if msg.wparam != MSG_COOKIE {
+            None
+        } else if msg.msg == msg_id_wake() {
+            let mut lparam = msg.lparam;
+            // Tasks polled by this batch, empty without batching.
+            let mut polled = Vec::new();
+            for _ in 1..WAKE_BATCH_SIZE.get() {
+                if !run_task(lparam) {
+                    return Some(0);
+                }
+                polled.push(lparam);
+                // Only wake messages of this window, other messages are
+                // retrieved by the message loop after the batch.
+                let mut next = MaybeUninit::uninit();
+                let found = unsafe {
+                    PeekMessageW(next.as_mut_ptr(), msg.hwnd, msg.msg, msg.msg, PM_NOREMOVE)
+                };
+                if found == 0 {
+                    return Some(0);
+                }
+                let mut next = unsafe { next.assume_init() };
+                // A task woken again while in this batch, e.g. by
+                // `yield_now()`, must go through the message loop first.
+                if next.wParam != MSG_COOKIE || polled.contains(&next.lParam) {
+                    return Some(0);
+                }
+                unsafe { PeekMessageW(&mut next, msg.hwnd, msg.msg, msg.msg, PM_REMOVE) };
+                lparam = next.lParam;
+            }
+            run_task(lparam);
+            Some(0)
+        } else if msg.msg == msg_id_spawn() {
+            let spawn_fn = unsafe { Box::from_raw(msg.lparam as *mut SpawnFn) };
+            if let Err(panic_payload) = panic::catch_unwind(AssertUnwindSafe(spawn_fn)) {
                 PANIC_PAYLOAD.set(Some(panic_payload));
             }
             Some(0)
         } else {
             None
         }
-    })
-    .unwrap();
+    }));
+}
+
+fn executor_hwnd() -> Result<HWND, WindowCreationError> {
+    EXECUTOR_WINDOW.with_borrow(|w| w.as_ref().map(Window::hwnd).map_err(|err| *err))
+}
+
+// Destroys the executor window like the exit of the thread does, so that
+// tests can wake tasks afterwards.
+#[cfg(all(test, not(feature = "apc-wake")))]
+fn destroy_executor_window() {
+    let destroyed = Err(WindowCreationError {
+        code: 0,
+        stage: util::CreateStage::CreateWindow,
+    });
+    drop(EXECUTOR_WINDOW.replace(destroyed));
+}
+
+// Drops the tasks and spawn closures of the messages still queued for the
+// executor window, which is destroyed when the thread exits. They would be
+// leaked with the message queue otherwise. Dropping a task drops its future,
+// which may wake other tasks and queue more messages.
+fn drop_queued_messages(hwnd: HWND) {
+    let (first, last) = executor_msg_range();
+    let mut msg = MaybeUninit::uninit();
+    while unsafe { PeekMessageW(msg.as_mut_ptr(), hwnd, first, last, PM_REMOVE) } != 0 {
+        let msg = unsafe { msg.assume_init_ref() };
+        if msg.wParam != MSG_COOKIE {
+            continue;
+        }
+        // There is nobody left to propagate a panic to.
+        let _ = panic::catch_unwind(|| unsafe {
+            if msg.message == msg_id_wake() {
+                let runnable_ptr = NonNull::new_unchecked(msg.lParam as *mut _);
+                drop(Runnable::<TaskMetadata>::from_raw(runnable_ptr));
+            } else if msg.message == msg_id_spawn() {
+                drop(Box::from_raw(msg.lParam as *mut SpawnFn));
+            }
+        });
+    }
+}
+
+// Runs the task of a wake message, returns `false` when it panicked.
+fn run_task(lparam: isize) -> bool {
+    let runnable = unsafe {
+        let runnable_ptr = NonNull::new_unchecked(lparam as *mut _);
+        Runnable::<TaskMetadata>::from_raw(runnable_ptr)
+    };
+    if let Err(panic_payload) = panic::catch_unwind(|| runnable.run()) {
+        PANIC_PAYLOAD.set(Some(panic_payload));
+        return false;
+    }
+    true
+}
+
+// Runs the task of a wake APC.
+#[cfg(feature = "apc-wake")]
+unsafe extern "system" fn apc_run_task(data: usize) {
+    run_task(data as isize);
+}
+
+// Runs the tasks woken with APCs since the last alertable wait.
+fn run_wake_apcs() {
+    #[cfg(feature = "apc-wake")]
+    {
+        unsafe { SleepEx(0, TRUE) };
+        resume_task_panic();
+    }
+}
+
+/// Sets the maximum number of tasks polled per wake message on the current
+/// thread, 1 by default.
+///
+/// With a batch size greater than 1, the executor retrieves further pending
+/// wake messages with `PeekMessageW()` after polling a task and polls their
+/// tasks right away, saving a round trip through the message loop for each.
+/// Other messages are serviced after each batch, so a larger size delays
+/// them for longer. Wake messages of a batch skip other messages posted
+/// before them and are not passed to the filter closure of
+/// [`MessageLoop::run`]. A batch ends at the wake message of a task it
+/// polled already, so a task that wakes itself (e.g. with [`yield_now()`])
+/// is polled at most once per batch and resumes only after the message loop
+/// retrieved the next message. Values below 1 are treated as 1.
+///
+/// Has no effect with the `apc-wake` feature, all queued wake APCs run in one
+/// go.
+pub fn set_wake_batch_size(size: usize) {
+    WAKE_BATCH_SIZE.set(size.max(1));
+}
+
+/// Runs the woken tasks of the current thread without processing any other
+/// message.
+///
+/// Tasks are polled by the window procedure of a hidden window, so any
+/// message loop of the thread runs them, also one of another framework or
+/// executor. This function is for hosts that do not dispatch all messages,
+/// e.g. because they filter `PeekMessage()` by window: It retrieves and
+/// dispatches only the wake messages (and tasks spawned with
+/// [`RemoteHandle::spawn_on`]), other messages stay queued for the host.
+/// Tasks woken while running are run in the same call.
+///
+/// A panic of a task is propagated to the caller. Does nothing when the
+/// executor could not be set up for the thread (see [`try_spawn_local()`]),
+/// no task can run on it then.
+pub fn run_pending_tasks() {
+    run_wake_apcs();
+    let Ok(hwnd) = executor_hwnd() else {
+        return;
+    };
+    let (first, last) = executor_msg_range();
+    let mut msg = MaybeUninit::uninit();
+    while unsafe { PeekMessageW(msg.as_mut_ptr(), hwnd, first, last, PM_REMOVE) } != 0 {
+        unsafe { DispatchMessageW(msg.as_ptr()) };
+        resume_task_panic();
+    }
+}
+
+/// Identifies a task among the tasks of its thread.
+///
+/// Returned by [`JoinHandle::id()`] and passed to the hook registered with
+/// [`MessageLoopConfig::on_poll`]. Ids are not reused, tasks of different
+/// threads may share the same id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn next() -> Self {
+        Self(NEXT_TASK_ID.replace(NEXT_TASK_ID.get() + 1))
+    }
+}
+
+/// Error returned when awaiting a [`JoinHandle`] of a task that did not
+/// complete successfully.
+pub struct JoinError {
+    // `None` when the task was aborted.
+    panic_payload: Option<PanicPayload>,
+    task_name: Option<Arc<str>>,
+}
+
+impl JoinError {
+    /// Returns `true` if the task was aborted with [`JoinHandle::abort`].
+    pub fn is_aborted(&self) -> bool {
+        self.panic_payload.is_none()
+    }
+
+    /// Returns `true` if the task panicked.
+    pub fn is_panic(&self) -> bool {
+        self.panic_payload.is_some()
+    }
+
+    /// Returns the payload of the panic, `None` when the task was aborted.
+    ///
+    /// Use [`std::panic::resume_unwind`] to continue the panic on the current
+    /// thread.
+    pub fn into_panic(self) -> Option<Box<dyn Any + Send + 'static>> {
+        self.panic_payload
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.panic_payload, &self.task_name) {
+            (None, None) => f.write_str("JoinError::Aborted"),
+            (None, Some(name)) => write!(f, "JoinError::Aborted({name:?})"),
+            (Some(_), None) => f.write_str("JoinError::Panic(..)"),
+            (Some(_), Some(name)) => write!(f, "JoinError::Panic({name:?}, ..)"),
+        }
+    }
 }
 
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task ")?;
+        if let Some(name) = &self.task_name {
+            write!(f, "{name:?} ")?;
+        }
+        match &self.panic_payload {
+            None => f.write_str("was aborted"),
+            Some(_) => f.write_str("panicked"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
 /// An owned permission to join on a task (await its termination).
 ///
+/// Awaiting the handle returns the output of the task or a [`JoinError`]
+/// when the task panicked or was aborted.
+///
 /// If a `JoinHandle` is dropped, then its task continues running in the
-/// background and its return value is lost.
+/// background and its return value is lost. A panic of such a detached task is
+/// propagated to the message loop instead, also when the task panicked before
+/// the handle was dropped.
+///
+/// The handle is `Send` when `T` is, so it can be awaited on another thread.
+/// The task itself stays on the thread it was spawned on.
 pub struct JoinHandle<T> {
-    task: ManuallyDrop<async_task::Task<T>>,
+    // `None` after the task was aborted.
+    task: Cell<Option<Task<T>>>,
+    // Shared with `JoinError`, which must be `Send`.
+    name: Option<Arc<str>>,
+    id: TaskId,
 }
 
 // Keep the task running when dropped.
 impl<T> Drop for JoinHandle<T> {
     fn drop(&mut self) {
-        let task = unsafe { ManuallyDrop::take(&mut self.task) };
-        task.detach();
+        let Some(mut task) = self.task.take() else {
+            return;
+        };
+        if !task.is_finished() {
+            task.metadata()
+                .propagate_panic
+                .store(true, Ordering::Relaxed);
+            task.detach();
+        } else if let Ok(Err(panic_payload)) = poll_ready(&mut task) {
+            // Panicked before the handle was dropped, propagate the panic to
+            // the message loop like the task would when detached. The payload
+            // is dropped when the thread exits.
+            let _ = PANIC_PAYLOAD.try_with(|p| p.set(Some(panic_payload)));
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Aborts the task.
+    ///
+    /// The task is not polled again. Its future is dropped by the message loop
+    /// of the task's thread, which requires the loop to process one more
+    /// message (the future may not be `Send`). The return value is lost if the
+    /// task has completed already.
+    ///
+    /// Awaiting the handle after an abort returns a [`JoinError`].
+    pub fn abort(&self) {
+        // No need to wake anyone: Awaiting the handle requires exclusive
+        // access, so there cannot be a pending `poll()` right now.
+        drop(self.task.take());
+    }
+
+    /// Lets the task run in the background, the same as dropping the handle.
+    ///
+    /// The output of the task is dropped as soon as it completes. A panic is
+    /// propagated to the message loop.
+    pub fn detach(self) {
+        drop(self);
+    }
+
+    /// Returns `true` if the task has completed or was aborted.
+    ///
+    /// Does not require a running message loop.
+    pub fn is_finished(&self) -> bool {
+        let task = self.task.take();
+        let finished = task.as_ref().is_none_or(|task| task.is_finished());
+        self.task.set(task);
+        finished
+    }
+
+    /// Returns the name of the task when spawned with [`spawn_named()`].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the id of the task.
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Returns the result of the task if it has finished already, without
+    /// waiting.
+    ///
+    /// Otherwise returns `None` and the task keeps running in the background,
+    /// like when the handle is dropped. Does not require a running message
+    /// loop.
+    pub fn now_or_never(self) -> Option<Result<T, JoinError>> {
+        poll_ready(self).ok()
     }
 }
 
 impl<T> Future for JoinHandle<T> {
-    type Output = T;
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let Some(task) = this.task.get_mut() else {
+            return Poll::Ready(Err(JoinError {
+                panic_payload: None,
+                task_name: this.name.clone(),
+            }));
+        };
+        let result = ready!(pin!(task).poll(cx));
+        // The output is taken, nothing left to propagate when dropped.
+        *this.task.get_mut() = None;
+        Poll::Ready(result.map_err(|panic_payload| JoinError {
+            panic_payload: Some(panic_payload),
+            task_name: this.name.clone(),
+        }))
+    }
+}
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        pin!(&mut *self.task).poll(cx)
+// Counts a task as live from spawning until its future is dropped.
+struct TaskCountGuard;
+
+impl TaskCountGuard {
+    fn new() -> Self {
+        #[cfg(feature = "task-count")]
+        TASK_COUNT.set(TASK_COUNT.get() + 1);
+        Self
+    }
+}
+
+impl Drop for TaskCountGuard {
+    fn drop(&mut self) {
+        // The counter is gone already when a task is dropped during thread exit.
+        #[cfg(feature = "task-count")]
+        let _ = TASK_COUNT.try_with(|count| count.set(count.get() - 1));
+        #[cfg(feature = "task-count")]
+        if let Some(waker) = TASK_COUNT_WAKER.try_with(Cell::take).ok().flatten() {
+            waker.wake();
+        }
+    }
+}
+
+// Counts a task as being polled while alive.
+struct PollDepthGuard;
+
+impl PollDepthGuard {
+    fn new() -> Self {
+        #[cfg(feature = "task-count")]
+        POLL_DEPTH.set(POLL_DEPTH.get() + 1);
+        Self
+    }
+}
+
+impl Drop for PollDepthGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "task-count")]
+        POLL_DEPTH.set(POLL_DEPTH.get() - 1);
     }
 }
 
-unsafe fn spawn_unchecked_lifetime<T>(future: impl Future<Output = T>) -> JoinHandle<T> {
-    let hwnd = EXECUTOR_WINDOW.with(|w| w.hwnd());
+/// Returns the number of live tasks on the current thread.
+///
+/// A task is live from the moment it is spawned until it completes or is
+/// aborted. Detached tasks count until they complete. The future passed to
+/// [`block_on()`] counts as task too.
+///
+/// Requires the `task-count` feature, returns 0 otherwise.
+pub fn task_count() -> usize {
+    #[cfg(feature = "task-count")]
+    return TASK_COUNT.get();
+    #[cfg(not(feature = "task-count"))]
+    0
+}
+
+unsafe fn spawn_unchecked_lifetime<T>(
+    future: impl Future<Output = T>,
+    propagate_panic: bool,
+) -> JoinHandle<T> {
+    #[cfg(not(feature = "apc-wake"))]
+    let hwnd = executor_hwnd().unwrap();
+    #[cfg(not(feature = "apc-wake"))]
+    let msg_id_wake = msg_id_wake();
+    #[cfg(not(feature = "apc-wake"))]
+    let thread_id = GetCurrentThreadId();
+    #[cfg(feature = "apc-wake")]
+    let thread = WAKE_THREAD.with(Arc::clone);
+
+    // Moved into the task future, so it is dropped even when never polled.
+    let count = TaskCountGuard::new();
+
+    let id = TaskId::next();
+    let metadata = TaskMetadata {
+        propagate_panic: AtomicBool::new(propagate_panic),
+        id,
+    };
 
     // SAFETY: The `future` does not need to be `Send` because the thread that
     // receives the runnable is our own, meaning the runniable is also dropped
     // on original thread.
     let (runnable, task) = unsafe {
-        async_task::spawn_unchecked(future, move |runnable: Runnable| {
-            PostMessageA(hwnd, MSG_ID_WAKE, 0, runnable.into_raw().as_ptr() as _);
-        })
+        async_task::Builder::new()
+            .metadata(metadata)
+            .spawn_unchecked(
+                |metadata| async move {
+                    let _count = count;
+                    // Dropped after the future.
+                    let locals = TaskLocals::default();
+                    let mut future = pin!(future);
+                    poll_fn(|cx| {
+                        let _depth = PollDepthGuard::new();
+                        let _locals = task_local::enter(&locals);
+                        if let Some(hook) = POLL_HOOK.get() {
+                            hook(metadata.id);
+                        }
+                        POLLS_SINCE_UI.set(POLLS_SINCE_UI.get() + 1);
+                        match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                            Ok(poll) => poll.map(Ok),
+                            Err(panic_payload)
+                                if metadata.propagate_panic.load(Ordering::Relaxed) =>
+                            {
+                                panic::resume_unwind(panic_payload)
+                            }
+                            Err(panic_payload) => Poll::Ready(Err(panic_payload)),
+                        }
+                    })
+                    .await
+                },
+                move |runnable: Runnable<TaskMetadata>| {
+                    #[cfg(not(feature = "apc-wake"))]
+                    {
+                        let runnable = runnable.into_raw();
+                        if PostMessageW(hwnd, msg_id_wake, MSG_COOKIE, runnable.as_ptr() as _) == 0
+                            && GetLastError() == ERROR_INVALID_WINDOW_HANDLE
+                            && GetCurrentThreadId() == thread_id
+                        {
+                            // The executor window was destroyed, the thread
+                            // exits. Dropping the runnable drops the future,
+                            // which is only sound on the task's own thread.
+                            // Wakes from other threads leak the task instead.
+                            drop(Runnable::<TaskMetadata>::from_raw(runnable));
+                        }
+                    }
+                    #[cfg(feature = "apc-wake")]
+                    QueueUserAPC(
+                        Some(apc_run_task),
+                        thread.0,
+                        runnable.into_raw().as_ptr() as _,
+                    );
+                },
+            )
     };
 
     // Trigger initial poll.
     runnable.schedule();
 
     JoinHandle {
-        task: ManuallyDrop::new(task),
+        task: Cell::new(Some(task)),
+        name: None,
+        id,
     }
 }
 
@@ -89,183 +664,1266 @@ unsafe fn spawn_unchecked_lifetime<T>(future: impl Future<Output = T>) -> JoinHa
 /// This function may be used to spawn tasks when the message loop is not
 /// running. The provided future will start running once the message loop
 /// is entered with [`block_on`] or [`MessageLoop::run`].
+///
+/// Tasks are polled in the order they were woken: Each wake up posts a
+/// message (or queues an APC with the `apc-wake` feature) and both queues
+/// are first in, first out. A task that wakes itself while being polled,
+/// e.g. with [`yield_now()`], is polled again only after all tasks woken
+/// before it. Continuously ready tasks are therefore polled round-robin,
+/// each once per round, also with [`set_wake_batch_size()`].
+///
+/// While the thread shuts down with `shutdown()` (`task-count` feature), the
+/// future is dropped without being spawned: Awaiting the returned handle
+/// returns a [`JoinError`] right away.
 pub fn spawn_local<T>(future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    #[cfg(feature = "task-count")]
+    if SHUTTING_DOWN.get() {
+        drop(future);
+        return JoinHandle {
+            task: Cell::new(None),
+            name: None,
+            id: TaskId::next(),
+        };
+    }
     // SAFETY: future is `'static`
-    unsafe { spawn_unchecked_lifetime(future) }
+    unsafe { spawn_unchecked_lifetime(future, false) }
 }
 
-/// Runs a future to completion on the calling threads message loop.
-///
-/// This runs the provided future on the current thread, blocking until it is
-/// complete. Also runs any tasks [`spawn`]ed from the same thread. Note that
-/// any spawned tasks will be suspended after `block_on` returns. Calling
-/// `block_on` again will resume previously spawned tasks.
-///
-/// # Panics
+/// Spawns a new future on the current thread, fails instead of panicking
+/// when the executor cannot be set up.
 ///
-/// Panics when quitting out of the message loop without the future being
-/// ready. This can happen when calling when the future or any spawned task
-/// calls the `PostQuitMessage()` winapi function.
-pub fn block_on<'a, T: 'a>(future: impl Future<Output = T> + 'a) -> T {
-    let msg_loop = &MessageLoop::new();
-
-    // Wrap the future so it quits the message loop when finished.
-    // SAFETY: All borrowed variables outlive the task itself because we only
-    // return from this function after the task has finished.
-    let task = unsafe {
-        spawn_unchecked_lifetime(async move {
-            let result = future.await;
-            msg_loop.quit();
-            result
-        })
-    };
-
-    msg_loop.run_loop(|_| FilterResult::Forward);
-
-    poll_ready(task).expect("received unexpected quit message")
+/// Behaves like [`spawn_local()`], which panics when the message-only window
+/// that receives the wake messages of the thread cannot be created. This
+/// happens when the process or the desktop ran out of window handles (the
+/// default quota is 10000 per process). The window is created once by the
+/// first task spawned on a thread, all tasks share it.
+pub fn try_spawn_local<T>(
+    future: impl Future<Output = T> + 'static,
+) -> Result<JoinHandle<T>, WindowCreationError> {
+    #[cfg(not(feature = "apc-wake"))]
+    executor_hwnd()?;
+    Ok(spawn_local(future))
 }
 
-fn poll_ready<T>(future: impl Future<Output = T>) -> Result<T, ()> {
-    // TODO: wait for https://github.com/rust-lang/rust/issues/98286 to land.
-    const NOOP_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
-        |_| RawWaker::new(ptr::null(), &NOOP_WAKER_VTABLE),
-        |_| (),
-        |_| (),
-        |_| (),
-    );
-    let noop_waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &NOOP_WAKER_VTABLE)) };
-    let future = pin!(future);
-    if let Poll::Ready(result) = future.poll(&mut Context::from_waker(&noop_waker)) {
-        Ok(result)
-    } else {
-        Err(())
-    }
+/// Spawns a new future on the current thread with a name for debugging.
+///
+/// Behaves like [`spawn_local()`]. The name is available from
+/// [`JoinHandle::name()`] and included in the [`JoinError`] of the task.
+pub fn spawn_named<T>(
+    name: impl Into<String>,
+    future: impl Future<Output = T> + 'static,
+) -> JoinHandle<T> {
+    let mut handle = spawn_local(future);
+    handle.name = Some(name.into().into());
+    handle
 }
 
-/// Return value of the filter closure passed to [`MessageLoop::run`].
+/// How a task spawned with [`spawn_with_on_done()`] terminated.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum FilterResult {
-    /// The message is forwarded to the window procedure.
-    Forward,
+pub enum TaskResult {
+    /// The future completed, even when its output is never received.
+    Completed,
 
-    /// The message is dropped and not forwarded to the window procedure.
-    Drop,
-}
+    /// The future was dropped before completion, with [`JoinHandle::abort`]
+    /// or when the thread exits.
+    Aborted,
 
-/// Abstract representation of a message loop.
-///
-/// Not directly constructible, use [`MessageLoop::run`] to create a message
-/// loop. The message loop struct is used to control the message loop behavior
-/// by passing it as an argument to the filter closure of [`MessageLoop::run`].
-pub struct MessageLoop {
-    quit: Cell<bool>,
+    /// The future panicked.
+    Panicked,
 }
 
-impl MessageLoop {
-    fn new() -> Self {
-        Self {
-            quit: Cell::new(false),
+// Calls the callback with `TaskResult::Aborted` when dropped before completion.
+struct OnDone<F: FnOnce(TaskResult)>(Option<F>);
+
+impl<F: FnOnce(TaskResult)> OnDone<F> {
+    fn call(&mut self, result: TaskResult) {
+        if let Some(on_done) = self.0.take() {
+            on_done(result);
         }
     }
+}
 
-    fn run_loop(&self, filter: impl Fn(&MSG) -> FilterResult) {
-        while !self.quit.get() {
-            unsafe {
-                let mut msg = MaybeUninit::uninit();
-                if GetMessageA(msg.as_mut_ptr(), ptr::null_mut(), 0, 0) == 0 {
-                    return;
-                }
-                let msg = msg.assume_init();
+impl<F: FnOnce(TaskResult)> Drop for OnDone<F> {
+    fn drop(&mut self) {
+        self.call(TaskResult::Aborted);
+    }
+}
 
-                if filter(&msg) == FilterResult::Forward {
-                    TranslateMessage(&msg);
-                    DispatchMessageA(&msg);
-                }
-                if let Some(panic_payload) = PANIC_PAYLOAD.take() {
+/// Spawns a new future on the current thread and calls `on_done` once the
+/// task terminated.
+///
+/// Behaves like [`spawn_local()`]. `on_done` runs on the current thread right
+/// after the future completed, panicked or was dropped, whichever happens
+/// first, and before a panic is propagated. Useful to release resources tied
+/// to the task, no matter if its handle is awaited, dropped or aborted.
+pub fn spawn_with_on_done<T>(
+    future: impl Future<Output = T> + 'static,
+    on_done: impl FnOnce(TaskResult) + 'static,
+) -> JoinHandle<T> {
+    let mut on_done = OnDone(Some(on_done));
+    spawn_local(async move {
+        let mut future = pin!(future);
+        let output = poll_fn(|cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+                Ok(poll) => poll,
+                Err(panic_payload) => {
+                    on_done.call(TaskResult::Panicked);
                     panic::resume_unwind(panic_payload)
                 }
             }
-        }
-    }
+        })
+        .await;
+        on_done.call(TaskResult::Completed);
+        output
+    })
+}
 
-    /// Runs the message loop with a filter closure to inspect and drop messages
-    /// before they are dispatched to their respective window procedure.
-    ///
-    /// Use the [`FilterResult`] return value to control how the message is
-    /// handled. The first argument to the filter closure is the [`MessageLoop`]
-    /// struct itself, which can be used to quit out of the message loop.
-    ///
-    /// Like [`block_on`] this function runs any tasks [`spawn`]ed from the same
-    /// thread. Any spawned tasks will be suspended when the `run_message_loop`
-    /// returns. Be careful not to drop messages not belonging to a window you
-    /// control or you might risk suspending a task indefinitely when dropping
-    /// its wake message.
-    ///
-    /// `run_message_loop` installs a [`WH_MSGFILTER`] hook to allow inspections
-    /// of messages while modal windows are open.
-    ///
-    /// # Panics and Reentrancy
-    ///
-    /// Panics when called from within another `run_message_loop` filter closure.
-    /// A call to [`block_on()`] from within the filter closure creates a nested
-    /// message loop which causes the filter closure to be reentered when a modal
-    /// window is open.
-    ///
-    /// [`WH_MSGFILTER`]: (https://learn.microsoft.com/en-us/windows/win32/winmsg/about-hooks#wh_msgfilter-and-wh_sysmsgfilter)
-    pub fn run(filter: impl Fn(&MessageLoop, &MSG) -> FilterResult) {
-        let msg_loop = MessageLoop::new();
+/// A `Send` handle to join on a task spawned with [`spawn_sync()`].
+///
+/// Like a [`JoinHandle`], this handle can be moved to and awaited on another
+/// thread, e.g. by a [`block_on()`] running there. Unlike it, it does not
+/// share the task but only receives its output. The task itself remains
+/// confined to the thread it was spawned on: Its future is polled and dropped
+/// there only, which requires the spawning thread to keep running its message
+/// loop. Only the output crosses threads, handed over behind a mutex.
+///
+/// Dropping the handle detaches the task. There is no way to abort the task
+/// from another thread.
+pub struct SyncJoinHandle<T> {
+    receiver: oneshot::Receiver<Result<T, JoinError>>,
+}
 
-        // Any modal window (i.e. a right-click menu) blocks the main message loop
-        // and dispatches messages internally. To keep the executor running use a
-        // hook to get access to modal windows' internal message loop.
-        // SAFETY: The Drop implementation of MsgFilterHook unregisters the hook,
-        // ensuring that dispatchers will not be called after the end of the scope.
-        let _hook = unsafe {
-            MsgFilterHook::register(|msg| {
-                panic::catch_unwind(AssertUnwindSafe(|| {
-                    let filter_result = filter(&msg_loop, msg);
-                    // When quit() was called it has no real effect because we
-                    // are running in a modal loop. Post a quit message to exit
-                    // the message loop that is not under our control ASAP.
-                    if msg_loop.quit.get() {
-                        PostMessageA(msg.hwnd, WM_QUIT, 0, 0);
-                    }
-                    filter_result == FilterResult::Drop
-                }))
-                .unwrap_or_else(|payload| {
-                    PANIC_PAYLOAD.with(|panic_payload| {
-                        panic_payload.set(Some(payload));
-                    });
-                    // Also exit the modal loop ASAP when a panic occurs.
-                    PostMessageA(msg.hwnd, WM_QUIT, 0, 0);
-                    false
-                })
-            })
-        };
-        msg_loop.run_loop(|msg| filter(&msg_loop, msg));
-    }
+impl<T> Future for SyncJoinHandle<T> {
+    type Output = Result<T, JoinError>;
 
-    /// Quits the message loop as soon as possible.
-    pub fn quit(&self) {
-        self.quit.set(true);
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The sender is dropped without sending when the task is dropped
+        // before completion, i.e. when the spawning thread exits.
+        Pin::new(&mut self.receiver).poll(cx).map(|result| {
+            result.unwrap_or(Err(JoinError {
+                panic_payload: None,
+                task_name: None,
+            }))
+        })
     }
+}
 
-    /// Quits the message loop when there are no more messages to process.
-    pub fn quit_when_idle(&self) {
-        unsafe { PostQuitMessage(0) };
-    }
+/// Spawns a new future on the current thread and returns a `Send` handle for
+/// its output.
+///
+/// Behaves like [`spawn_local()`], except that the returned
+/// [`SyncJoinHandle`] may be awaited from any thread. The future does not need
+/// to be `Send`, only its output.
+pub fn spawn_sync<T: Send + 'static>(
+    future: impl Future<Output = T> + 'static,
+) -> SyncJoinHandle<T> {
+    let (sender, receiver) = oneshot();
+    let task = spawn_local(future);
+    drop(spawn_local(async move {
+        let _ = sender.send(task.await);
+    }));
+    SyncJoinHandle { receiver }
 }
 
-#[cfg(test)]
-mod test {
-    use std::{ffi::CStr, future::poll_fn};
+/// Yields execution back to the message loop.
+///
+/// The task is rescheduled through the message queue, so at least one message
+/// loop iteration happens before the task resumes. Use it to break up long
+/// running computations.
+///
+/// Wake messages are posted messages, which Windows retrieves before input
+/// and paint messages. To let pending UI messages drain anyway, the message
+/// loops of this crate dispatch pending input and paint messages (up to 64)
+/// right after the message that polled the yielding task, before retrieving its
+/// wake message. Other message loops (e.g. of modal windows) resume the task
+/// with its wake message only.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            UI_YIELD.set(true);
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await;
+}
 
-    use windows_sys::Win32::Foundation::HWND;
+/// Handle to spawn tasks on the message loop of another thread.
+///
+/// Created with [`remote_handle()`] on the thread that runs the tasks. The
+/// handle is `Send` and can be cloned and passed to other threads freely.
+#[derive(Debug, Clone)]
+pub struct RemoteHandle {
+    hwnd: HWND,
+}
 
-    use super::*;
+// SAFETY: The window handle is only passed to `PostMessageW()`, which is safe
+// to call from any thread.
+unsafe impl Send for RemoteHandle {}
+unsafe impl Sync for RemoteHandle {}
 
-    fn post_thread_message(msg: u32) {
-        unsafe { PostMessageA(ptr::null_mut(), msg, 0, 0) };
+impl RemoteHandle {
+    /// Spawns a task on the thread the handle was created on.
+    ///
+    /// The `constructor` closure is sent to the target thread, where it
+    /// creates the future and spawns it with [`spawn_local()`]. Only the
+    /// closure must be `Send`, the future it returns does not. The output of
+    /// the task is discarded.
+    ///
+    /// Returns an error when the target thread has exited already. The task
+    /// starts running when the target thread runs its message loop.
+    pub fn spawn_on<F>(&self, constructor: impl FnOnce() -> F + Send + 'static) -> io::Result<()>
+    where
+        F: Future + 'static,
+    {
+        let spawn_fn: SpawnFn = Box::new(move || drop(spawn_local(constructor())));
+        let spawn_fn = Box::into_raw(Box::new(spawn_fn));
+        if unsafe { PostMessageW(self.hwnd, msg_id_spawn(), MSG_COOKIE, spawn_fn as _) } == 0 {
+            drop(unsafe { Box::from_raw(spawn_fn) });
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Returns a [`RemoteHandle`] to spawn tasks on the current thread from other
+/// threads.
+pub fn remote_handle() -> RemoteHandle {
+    RemoteHandle {
+        hwnd: executor_hwnd().unwrap(),
+    }
+}
+
+/// Spawns the future on the thread of the handle with
+/// [`RemoteHandle::spawn_on`].
+#[cfg(feature = "futures-spawn")]
+impl futures_task::Spawn for RemoteHandle {
+    fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        self.spawn_on(move || future)
+            .map_err(|_| SpawnError::shutdown())
+    }
+}
+
+/// Handle to spawn tasks on the current thread through the
+/// [`LocalSpawn`](futures_task::LocalSpawn) trait of the `futures` crate.
+///
+/// Created with [`local_spawner()`], the handle cannot be sent to other
+/// threads. Use [`RemoteHandle`] for that, which implements
+/// [`Spawn`](futures_task::Spawn).
+///
+/// Requires the `futures-spawn` feature.
+#[cfg(feature = "futures-spawn")]
+#[derive(Debug, Clone)]
+pub struct LocalSpawner {
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+/// Returns a [`LocalSpawner`] for the current thread.
+///
+/// Requires the `futures-spawn` feature.
+#[cfg(feature = "futures-spawn")]
+pub fn local_spawner() -> LocalSpawner {
+    LocalSpawner {
+        _not_send: std::marker::PhantomData,
+    }
+}
+
+/// Spawns the future as detached task with [`spawn_local()`]. Fails while the
+/// thread shuts down with `shutdown()` (`task-count` feature).
+#[cfg(feature = "futures-spawn")]
+impl futures_task::LocalSpawn for LocalSpawner {
+    fn spawn_local_obj(&self, future: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
+        self.status_local()?;
+        spawn_local(future);
+        Ok(())
+    }
+
+    fn status_local(&self) -> Result<(), SpawnError> {
+        #[cfg(feature = "task-count")]
+        if SHUTTING_DOWN.get() {
+            return Err(SpawnError::shutdown());
+        }
+        Ok(())
+    }
+}
+
+/// Runs a future to completion on the calling threads message loop.
+///
+/// This runs the provided future on the current thread, blocking until it is
+/// complete. Also runs any tasks [`spawn`]ed from the same thread. Note that
+/// any spawned tasks will be suspended after `block_on` returns. Calling
+/// `block_on` again will resume previously spawned tasks.
+///
+/// To wait for a task spawned earlier, pass its [`JoinHandle`]: The loop runs
+/// all tasks until that one terminated and returns its result.
+///
+/// ```
+/// use winmsg_executor::{block_on, spawn_local};
+///
+/// let mut tasks: Vec<_> = (0..3).map(|i| spawn_local(async move { i })).collect();
+/// assert_eq!(block_on(tasks.remove(1)).unwrap(), 1);
+/// ```
+///
+/// It is fine to call `block_on` while a message loop is running already, i.e.
+/// from within a task or the filter closure of [`MessageLoop::run`]. A nested
+/// message loop then drives the future to completion and keeps running the
+/// other tasks of the thread in the meantime.
+///
+/// # Reentrancy
+///
+/// A nested `block_on` is sound but easy to get wrong:
+///
+/// - The calling task (or filter closure) is suspended until the nested
+///   future completes. If that future waits for the calling task, the thread
+///   deadlocks while still processing messages.
+/// - Other tasks and window procedures run while the caller is suspended in
+///   the middle of its work. `RefCell` borrows held across the call panic
+///   when those reenter the same state.
+/// - The filter closure of the outer [`MessageLoop::run`] only sees messages
+///   of the nested loop when a modal window is open.
+/// - Quitting the outer loop has no effect until the nested loop returns. A
+///   quit message (posted with `PostQuitMessage()`) received by the nested
+///   loop does not end it: The quit message is posted again once the future
+///   completed, which then ends the outer loop.
+///
+/// # Panics
+///
+/// Panics when quitting out of the message loop without the future being
+/// ready, unless it is nested in another message loop. This can happen when
+/// the future or any spawned task calls the `PostQuitMessage()` winapi
+/// function. Tasks woken before the quit message was received are polled
+/// once more before giving up: When the future completes, its output is
+/// returned and the quit message is posted again.
+pub fn block_on<'a, T: 'a>(future: impl Future<Output = T> + 'a) -> T {
+    BlockOnConfig::new().block_on(future)
+}
+
+/// Builder for a [`block_on()`] with additional steps when the future
+/// completed.
+///
+/// ```
+/// use winmsg_executor::{spawn_local, BlockOnConfig};
+///
+/// let task = BlockOnConfig::new()
+///     .drain_woken_tasks()
+///     .block_on(async { spawn_local(async { 1 }) });
+/// assert!(task.is_finished());
+/// ```
+#[derive(Debug, Default)]
+pub struct BlockOnConfig {
+    drain_woken_tasks: bool,
+}
+
+impl BlockOnConfig {
+    /// Creates a configuration for a plain [`block_on()`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Polls the other tasks of the thread that are woken already once more
+    /// before returning.
+    ///
+    /// Without draining, tasks woken while the future completes are suspended
+    /// until the next message loop runs, even when they would complete right
+    /// away. Tasks woken while draining, e.g. by [`yield_now()`], stay
+    /// suspended. With a [`set_wake_batch_size()`] greater than 1, up to one
+    /// batch of those is polled too. With the `apc-wake` feature, all queued
+    /// wake APCs run, including the ones queued while draining.
+    pub fn drain_woken_tasks(mut self) -> Self {
+        self.drain_woken_tasks = true;
+        self
+    }
+
+    /// Runs a future to completion like [`block_on()`] with this
+    /// configuration.
+    pub fn block_on<'a, T: 'a>(self, future: impl Future<Output = T> + 'a) -> T {
+        let msg_loop = &MessageLoop::new();
+
+        // Wrap the future so it quits the message loop when finished. A panic is
+        // propagated to the message loop, which unwinds out of this function.
+        // SAFETY: All borrowed variables outlive the task itself because we only
+        // return from this function after the task has finished.
+        let task = unsafe {
+            spawn_unchecked_lifetime(
+                async move {
+                    let result = future.await;
+                    msg_loop.quit();
+                    result
+                },
+                true,
+            )
+        };
+
+        // A nested loop keeps running the future when it receives a quit message,
+        // which is posted again for the outer loop once the future completed.
+        let mut exit_code = None;
+        while let Some(code) = msg_loop.run_loop(|_| FilterResult::Forward) {
+            exit_code = Some(code);
+            if !msg_loop.is_nested() {
+                // The future may have been woken right before the quit
+                // message was received, e.g. from another thread.
+                drain_woken_tasks();
+                break;
+            }
+        }
+        if self.drain_woken_tasks {
+            drain_woken_tasks();
+        }
+        // A nested loop only ends when the future completed. Without a result
+        // the outermost loop panics below, the quit message ends with it.
+        let result = poll_ready(task);
+        if let (Some(exit_code), Ok(_)) = (exit_code, &result) {
+            unsafe { PostQuitMessage(exit_code) };
+        }
+
+        match result.expect("received unexpected quit message") {
+            Ok(result) => result,
+            Err(_) => unreachable!("panics are propagated to the message loop"),
+        }
+    }
+}
+
+// Runs the tasks with queued wake messages, stops at a marker message posted
+// behind them so that tasks which wake themselves are polled once only.
+fn drain_woken_tasks() {
+    #[cfg(not(feature = "apc-wake"))]
+    {
+        // Without the executor window there are no tasks to drain.
+        let Ok(hwnd) = executor_hwnd() else {
+            return;
+        };
+        let marker: SpawnFn = Box::new(|| ());
+        let marker = Box::into_raw(Box::new(marker));
+        if unsafe { PostMessageW(hwnd, msg_id_spawn(), MSG_COOKIE, marker as _) } == 0 {
+            drop(unsafe { Box::from_raw(marker) });
+            return;
+        }
+        let (first, last) = executor_msg_range();
+        let mut msg = MaybeUninit::uninit();
+        while unsafe { PeekMessageW(msg.as_mut_ptr(), hwnd, first, last, PM_REMOVE) } != 0 {
+            // Frees the marker when dispatched.
+            unsafe { DispatchMessageW(msg.as_ptr()) };
+            resume_task_panic();
+            let msg = unsafe { msg.assume_init_ref() };
+            if msg.message == msg_id_spawn() && msg.lParam == marker as isize {
+                break;
+            }
+        }
+    }
+    run_wake_apcs();
+}
+
+/// Waits for all other tasks of the current thread to complete, then quits
+/// the message loop.
+///
+/// New tasks are not accepted while waiting: [`spawn_local()`] (and the other
+/// spawn functions) drop their future right away. Tasks which wait for the
+/// calling task, i.e. the ones which run a nested message loop, are not
+/// waited for. Useful to let pending work, like async writes, complete before
+/// the application exits.
+///
+/// Gives up after `timeout`, when given, and returns [`timer::Elapsed`]. The
+/// remaining tasks are not aborted. In both cases the message loop is quit
+/// with a `WM_QUIT` message and an exit code of 0.
+///
+/// Requires the `task-count` feature.
+#[cfg(feature = "task-count")]
+pub async fn shutdown(timeout: Option<Duration>) -> Result<(), timer::Elapsed> {
+    struct ShuttingDown;
+
+    impl Drop for ShuttingDown {
+        fn drop(&mut self) {
+            SHUTTING_DOWN.set(false);
+        }
+    }
+
+    SHUTTING_DOWN.set(true);
+    let _shutting_down = ShuttingDown;
+    let drained = poll_fn(|cx| {
+        // The calling task and the tasks blocked on it are still alive.
+        if TASK_COUNT.get() <= POLL_DEPTH.get() {
+            Poll::Ready(())
+        } else {
+            TASK_COUNT_WAKER.set(Some(cx.waker().clone()));
+            Poll::Pending
+        }
+    });
+    let result = match timeout {
+        Some(timeout) => timer::timeout(timeout, drained).await,
+        None => {
+            drained.await;
+            Ok(())
+        }
+    };
+    unsafe { PostQuitMessage(0) };
+    result
+}
+
+/// What [`block_on_timeout()`] does with the future when the timeout elapses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnTimeout {
+    /// The future keeps running as detached task, see [`JoinHandle`].
+    Detach,
+
+    /// The future is aborted, see [`JoinHandle::abort`].
+    Abort,
+}
+
+/// Like [`block_on()`], but gives up after `duration`.
+///
+/// The future is spawned as task and raced against a [`timer::sleep()`].
+/// Returns [`timer::Elapsed`] when the timer fires first, without polling the
+/// future to completion. Depending on `on_timeout` the future is detached,
+/// continuing to run whenever the message loop of this thread runs again, or
+/// aborted.
+///
+/// # Panics
+///
+/// Panics like [`block_on()`] and when the future panics. Also panics when
+/// called while the thread shuts down with `shutdown()` (`task-count`
+/// feature), which does not accept the task for the future.
+pub fn block_on_timeout<T: 'static>(
+    future: impl Future<Output = T> + 'static,
+    duration: Duration,
+    on_timeout: OnTimeout,
+) -> Result<T, timer::Elapsed> {
+    let mut task = spawn_local(future);
+    match block_on(timer::timeout(duration, &mut task)) {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(err)) => match err.into_panic() {
+            Some(panic_payload) => panic::resume_unwind(panic_payload),
+            // Only aborted before it completes when not spawned at all.
+            None => panic!("block_on_timeout() called while the thread shuts down"),
+        },
+        Err(elapsed) => {
+            if on_timeout == OnTimeout::Abort {
+                task.abort();
+            }
+            Err(elapsed)
+        }
+    }
+}
+
+fn poll_ready<T>(future: impl Future<Output = T>) -> Result<T, ()> {
+    let future = pin!(future);
+    if let Poll::Ready(result) = future.poll(&mut Context::from_waker(Waker::noop())) {
+        Ok(result)
+    } else {
+        Err(())
+    }
+}
+
+fn peek_message() -> Option<MSG> {
+    unsafe {
+        let mut msg = MaybeUninit::uninit();
+        if PeekMessageW(msg.as_mut_ptr(), ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+            Some(msg.assume_init())
+        } else {
+            None
+        }
+    }
+}
+
+// Dispatches a message which passed the filter and propagates panics of tasks.
+fn dispatch(msg: &mut MSG, filter: impl Fn(&mut MSG) -> FilterResult) {
+    dispatch_message(msg, &filter);
+
+    let budget = POLL_BUDGET.get();
+    if UI_YIELD.take() || (budget != 0 && POLLS_SINCE_UI.get() >= budget) {
+        POLLS_SINCE_UI.set(0);
+        // Posted wake messages are retrieved before input and paint messages,
+        // which yielding or continuously woken tasks would starve otherwise.
+        for _ in 0..UI_DRAIN_LIMIT {
+            let Some(mut msg) = peek_ui_message() else {
+                break;
+            };
+            dispatch_message(&mut msg, &filter);
+        }
+    }
+}
+
+fn peek_ui_message() -> Option<MSG> {
+    unsafe {
+        let mut msg = MaybeUninit::uninit();
+        let flags = PM_REMOVE | PM_QS_INPUT | PM_QS_PAINT;
+        if PeekMessageW(msg.as_mut_ptr(), ptr::null_mut(), 0, 0, flags) != 0 {
+            Some(msg.assume_init())
+        } else {
+            None
+        }
+    }
+}
+
+fn dispatch_message(msg: &mut MSG, filter: impl Fn(&mut MSG) -> FilterResult) {
+    unsafe {
+        if filter(msg) == FilterResult::Forward {
+            TranslateMessage(msg);
+            DispatchMessageW(msg);
+        }
+    }
+    if let Some(hook) = MESSAGE_HOOK.get() {
+        hook(msg);
+    }
+    resume_task_panic();
+}
+
+// Resumes the panic of a task polled by the last dispatched message or APC.
+fn resume_task_panic() {
+    if let Some(panic_payload) = PANIC_PAYLOAD.take() {
+        panic::resume_unwind(panic_payload)
+    }
+}
+
+/// Return value of the filter closure passed to [`MessageLoop::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    /// The message is forwarded to the window procedure.
+    Forward,
+
+    /// The message is dropped and not forwarded to the window procedure.
+    Drop,
+
+    /// The message is dropped and the message loop quits, like a call to
+    /// [`MessageLoop::quit`] from within the filter closure.
+    Quit,
+}
+
+/// Timestamp and cursor position of a message.
+///
+/// Interprets the `time` and `pt` fields of a [`MSG`], for example to measure
+/// input latency from within the filter closure of [`MessageLoop::run`]. The
+/// raw message is still passed to the filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageInfo {
+    /// Time the message was posted in milliseconds since system start, as
+    /// returned by `GetTickCount()`. Wraps around after 49.7 days.
+    pub time: u32,
+
+    /// Cursor position in screen coordinates when the message was posted.
+    pub point: (i32, i32),
+}
+
+impl MessageInfo {
+    /// Returns the info of the message last retrieved by the message loop,
+    /// using `GetMessageTime()` and `GetMessagePos()`.
+    ///
+    /// Meant for window procedures, which do not receive the [`MSG`].
+    pub fn current() -> Self {
+        let pos = unsafe { GetMessagePos() };
+        Self {
+            time: unsafe { GetMessageTime() } as u32,
+            // Signed coordinates, negative on monitors left of or above the
+            // primary monitor.
+            point: ((pos & 0xFFFF) as i16 as i32, (pos >> 16) as i16 as i32),
+        }
+    }
+
+    /// Returns the time elapsed since the message was posted.
+    pub fn age(&self) -> Duration {
+        let now = unsafe { GetTickCount() };
+        Duration::from_millis(now.wrapping_sub(self.time).into())
+    }
+}
+
+impl From<&MSG> for MessageInfo {
+    fn from(msg: &MSG) -> Self {
+        Self {
+            time: msg.time,
+            point: (msg.pt.x, msg.pt.y),
+        }
+    }
+}
+
+/// Abstract representation of a message loop.
+///
+/// Not directly constructible, use [`MessageLoop::run`] to create a message
+/// loop. The message loop struct is used to control the message loop behavior
+/// by passing it as an argument to the filter closure of [`MessageLoop::run`].
+pub struct MessageLoop {
+    quit: Cell<bool>,
+    // Set while `run_loop()` waits for the next message.
+    retrieving: Cell<bool>,
+}
+
+impl Drop for MessageLoop {
+    fn drop(&mut self) {
+        LOOP_DEPTH.set(LOOP_DEPTH.get() - 1);
+    }
+}
+
+impl MessageLoop {
+    fn new() -> Self {
+        LOOP_DEPTH.set(LOOP_DEPTH.get() + 1);
+        Self {
+            quit: Cell::new(false),
+            retrieving: Cell::new(false),
+        }
+    }
+
+    // Runs inside of another message loop.
+    fn is_nested(&self) -> bool {
+        LOOP_DEPTH.get() > 1
+    }
+
+    // Posts a quit message received by a nested loop again, so that the
+    // outer loop quits too.
+    fn forward_quit(&self, exit_code: Option<i32>) -> Option<i32> {
+        if let Some(exit_code) = exit_code {
+            if self.is_nested() {
+                unsafe { PostQuitMessage(exit_code) };
+            }
+        }
+        exit_code
+    }
+
+    // Returns the exit code when the loop ended because of a `WM_QUIT` message.
+    fn run_loop(&self, filter: impl Fn(&mut MSG) -> FilterResult) -> Option<i32> {
+        // `GetMessageW()` does not run wake APCs, wait alertable instead.
+        #[cfg(feature = "apc-wake")]
+        return self.run_loop_with_idle(filter, || {});
+
+        #[cfg(not(feature = "apc-wake"))]
+        {
+            while !self.quit.get() {
+                let mut msg = unsafe {
+                    let mut msg = MaybeUninit::uninit();
+                    self.retrieving.set(true);
+                    let result = GetMessageW(msg.as_mut_ptr(), ptr::null_mut(), 0, 0);
+                    self.retrieving.set(false);
+                    if result == 0 {
+                        return Some(msg.assume_init().wParam as i32);
+                    }
+                    msg.assume_init()
+                };
+                dispatch(&mut msg, &filter);
+            }
+            None
+        }
+    }
+
+    // Like `peek_message()`, but marks the loop as retrieving.
+    fn peek(&self) -> Option<MSG> {
+        self.retrieving.set(true);
+        let msg = peek_message();
+        self.retrieving.set(false);
+        msg
+    }
+
+    // Dispatches messages until the queue is empty or `quit()` was called.
+    fn pump_loop(&self, filter: impl Fn(&mut MSG) -> FilterResult) -> ControlFlow<i32> {
+        loop {
+            run_wake_apcs();
+            if self.quit.get() {
+                return ControlFlow::Break(0);
+            }
+            let Some(mut msg) = peek_message() else {
+                return ControlFlow::Continue(());
+            };
+            if msg.message == WM_QUIT {
+                return ControlFlow::Break(msg.wParam as i32);
+            }
+            dispatch(&mut msg, &filter);
+        }
+    }
+
+    // Like `run_loop()`, but calls `idle` whenever the message queue is empty
+    // before waiting for new messages.
+    fn run_loop_with_idle(
+        &self,
+        filter: impl Fn(&mut MSG) -> FilterResult,
+        mut idle: impl FnMut(),
+    ) -> Option<i32> {
+        loop {
+            // Also between messages, so that a busy queue does not starve
+            // tasks woken with APCs.
+            run_wake_apcs();
+            if self.quit.get() {
+                return None;
+            }
+            match self.peek() {
+                Some(msg) if msg.message == WM_QUIT => return Some(msg.wParam as i32),
+                Some(mut msg) => dispatch(&mut msg, &filter),
+                None => {
+                    idle();
+                    if !self.quit.get() {
+                        // Returns right away when `idle` posted messages or
+                        // woke tasks, otherwise sleeps until a message arrives.
+                        unsafe {
+                            MsgWaitForMultipleObjectsEx(
+                                0,
+                                ptr::null(),
+                                INFINITE,
+                                QS_ALLINPUT,
+                                MWMO_INPUTAVAILABLE | MWMO_WAKE,
+                            )
+                        };
+                        resume_task_panic();
+                    }
+                }
+            }
+        }
+    }
+
+    // Like `run_loop()`, but retrieves messages in the range only. Quit
+    // messages are checked for separately, they might be outside of the range.
+    fn run_loop_in_range(
+        &self,
+        filter: impl Fn(&mut MSG) -> FilterResult,
+        first: u32,
+        last: u32,
+    ) -> Option<i32> {
+        loop {
+            run_pending_tasks();
+            if self.quit.get() {
+                return None;
+            }
+            let mut msg = MaybeUninit::uninit();
+            self.retrieving.set(true);
+            let found = unsafe {
+                PeekMessageW(msg.as_mut_ptr(), ptr::null_mut(), first, last, PM_REMOVE) != 0
+                    || PeekMessageW(
+                        msg.as_mut_ptr(),
+                        ptr::null_mut(),
+                        WM_QUIT,
+                        WM_QUIT,
+                        PM_REMOVE,
+                    ) != 0
+            };
+            self.retrieving.set(false);
+            if !found {
+                // Without `MWMO_INPUTAVAILABLE`, so that messages outside of
+                // the range, which were seen by `PeekMessageW()` already, do
+                // not end the wait.
+                unsafe {
+                    MsgWaitForMultipleObjectsEx(0, ptr::null(), INFINITE, QS_ALLINPUT, MWMO_WAKE)
+                };
+                resume_task_panic();
+                continue;
+            }
+            let mut msg = unsafe { msg.assume_init() };
+            if msg.message == WM_QUIT {
+                return Some(msg.wParam as i32);
+            }
+            // Queued after `run_pending_tasks()` returned, run like the other
+            // woken tasks without the filter.
+            if executor_hwnd() == Ok(msg.hwnd) {
+                unsafe { DispatchMessageW(&msg) };
+                resume_task_panic();
+                continue;
+            }
+            dispatch(&mut msg, &filter);
+        }
+    }
+
+    // Installs the `WH_MSGFILTER` hook for the duration of `run_loop`.
+    // Changes made to messages of modal loops are discarded.
+    //
+    // With `get_message_hook` a `WH_GETMESSAGE` hook is used instead, which
+    // filters all messages. The filter passed to `run_loop` then forwards
+    // everything to not filter messages twice.
+    fn with_hook<R>(
+        get_message_hook: bool,
+        filter: impl Fn(&MessageLoop, &mut MSG) -> FilterResult,
+        run_loop: impl FnOnce(&MessageLoop, &dyn Fn(&mut MSG) -> FilterResult) -> R,
+    ) -> R {
+        let msg_loop = MessageLoop::new();
+        let filter = |msg_loop: &MessageLoop, msg: &mut MSG| {
+            let filter_result = filter(msg_loop, msg);
+            if filter_result == FilterResult::Quit {
+                msg_loop.quit();
+            }
+            filter_result
+        };
+
+        // Any modal window (i.e. a right-click menu) blocks the main message loop
+        // and dispatches messages internally. To keep the executor running use a
+        // hook to get access to modal windows' internal message loop.
+        // SAFETY: The Drop implementation of MsgFilterHook unregisters the hook,
+        // ensuring that dispatchers will not be called after the end of the scope.
+        let handler = |msg: &MSG| {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                // Modal loops do not wait alertable, run the tasks woken in
+                // the meantime at least whenever a message arrives.
+                #[cfg(feature = "apc-wake")]
+                unsafe {
+                    SleepEx(0, TRUE)
+                };
+                let filter_result = filter(&msg_loop, &mut msg.clone());
+                // When quit() was called it has no real effect because we
+                // are running in a modal loop. Post a quit message to exit
+                // the message loop that is not under our control ASAP.
+                if msg_loop.quit.get() && !msg_loop.retrieving.get() {
+                    unsafe { PostMessageW(msg.hwnd, WM_QUIT, 0, 0) };
+                }
+                filter_result != FilterResult::Forward
+            }))
+            .unwrap_or_else(|payload| {
+                PANIC_PAYLOAD.with(|panic_payload| {
+                    panic_payload.set(Some(payload));
+                });
+                // Also exit the modal loop ASAP when a panic occurs. The
+                // panic is resumed right away when in our own loop.
+                if !msg_loop.retrieving.get() {
+                    unsafe { PostMessageW(msg.hwnd, WM_QUIT, 0, 0) };
+                }
+                false
+            })
+        };
+        let _hook = unsafe {
+            if get_message_hook {
+                MsgFilterHook::register_get_message(handler)
+            } else {
+                MsgFilterHook::register(handler)
+            }
+        };
+        if get_message_hook {
+            run_loop(&msg_loop, &|_| FilterResult::Forward)
+        } else {
+            run_loop(&msg_loop, &|msg| filter(&msg_loop, msg))
+        }
+    }
+
+    /// Runs the message loop with a filter closure to inspect and drop messages
+    /// before they are dispatched to their respective window procedure.
+    ///
+    /// Use the [`FilterResult`] return value to control how the message is
+    /// handled. The first argument to the filter closure is the [`MessageLoop`]
+    /// struct itself, which can be used to quit out of the message loop.
+    ///
+    /// Like [`block_on`] this function runs any tasks [`spawn`]ed from the same
+    /// thread. Any spawned tasks will be suspended when the `run_message_loop`
+    /// returns. Be careful not to drop messages not belonging to a window you
+    /// control or you might risk suspending a task indefinitely when dropping
+    /// its wake message.
+    ///
+    /// `run_message_loop` installs a [`WH_MSGFILTER`] hook to allow inspections
+    /// of messages while modal windows are open.
+    ///
+    /// Returns the exit code of the `WM_QUIT` message that ended the loop, as
+    /// posted by [`MessageLoop::quit_when_idle_with_code`] or the
+    /// `PostQuitMessage()` winapi function. Returns `None` when the loop was
+    /// ended with [`MessageLoop::quit`].
+    ///
+    /// # Panics and Reentrancy
+    ///
+    /// Calling `run` from within another filter closure creates a nested
+    /// message loop. While it runs, messages of modal windows are passed to
+    /// the inner filter closure first and then to the outer one, until a
+    /// filter drops the message. The same applies to a call to [`block_on()`]
+    /// from within the filter closure, which causes the filter closure to be
+    /// reentered when a modal window is open.
+    ///
+    /// A quit message ends the innermost message loop only. When it is nested,
+    /// the quit message is posted again on the way out, so that the outer
+    /// loops end one after another, as do modal loops of Windows. A nested
+    /// [`block_on()`] completes its future first. [`MessageLoop::quit`] ends
+    /// its own loop only.
+    ///
+    /// [`WH_MSGFILTER`]: (https://learn.microsoft.com/en-us/windows/win32/winmsg/about-hooks#wh_msgfilter-and-wh_sysmsgfilter)
+    pub fn run(filter: impl Fn(&MessageLoop, &MSG) -> FilterResult) -> Option<i32> {
+        Self::with_hook(
+            false,
+            |msg_loop, msg| filter(msg_loop, msg),
+            |msg_loop, filter| msg_loop.forward_quit(msg_loop.run_loop(filter)),
+        )
+    }
+
+    /// Like [`MessageLoop::run`], but the filter closure may modify messages
+    /// before they are dispatched, e.g. to remap virtual key codes of
+    /// `WM_KEYDOWN` messages before `TranslateMessage()` generates `WM_CHAR`.
+    ///
+    /// Changes to messages retrieved by the message loop of a modal window are
+    /// not applied. The `filter` is not reentered: Messages received by a
+    /// modal window opened from within the filter closure are forwarded
+    /// unfiltered.
+    pub fn run_mut(filter: impl FnMut(&MessageLoop, &mut MSG) -> FilterResult) -> Option<i32> {
+        let filter = RefCell::new(filter);
+        Self::with_hook(
+            false,
+            |msg_loop, msg| match filter.try_borrow_mut() {
+                Ok(mut filter) => filter(msg_loop, msg),
+                Err(_) => FilterResult::Forward,
+            },
+            |msg_loop, filter| msg_loop.forward_quit(msg_loop.run_loop(filter)),
+        )
+    }
+
+    /// Like [`MessageLoop::run`], but calls the `idle` closure whenever the
+    /// message queue has been drained.
+    ///
+    /// After `idle` returns the thread sleeps until the next message arrives,
+    /// so an idle loop does not spin the CPU. To run `idle` again soon, post a
+    /// message or wake a task from within it. The closure is not called while
+    /// a modal window runs its own message loop.
+    pub fn run_with_idle(
+        filter: impl Fn(&MessageLoop, &MSG) -> FilterResult,
+        mut idle: impl FnMut(&MessageLoop),
+    ) -> Option<i32> {
+        Self::with_hook(
+            false,
+            |msg_loop, msg| filter(msg_loop, msg),
+            |msg_loop, filter| {
+                msg_loop.forward_quit(msg_loop.run_loop_with_idle(filter, || idle(msg_loop)))
+            },
+        )
+    }
+
+    /// Processes all queued messages without blocking, for applications that
+    /// run their own loop, e.g. once per rendered frame.
+    ///
+    /// Messages are passed through `filter` and dispatched like in
+    /// [`MessageLoop::run`], which runs any tasks woken in the meantime.
+    /// Returns [`ControlFlow::Break`] with the exit code as soon as a `WM_QUIT`
+    /// message is received, or with 0 when the filter closure returned
+    /// [`FilterResult::Quit`]. Otherwise returns [`ControlFlow::Continue`]
+    /// once the queue is empty.
+    ///
+    /// Wake messages posted while pumping are processed in the same call. A
+    /// task that reschedules itself continuously, like a loop around
+    /// [`yield_now()`], keeps this function from returning.
+    pub fn pump_messages(filter: impl Fn(&MSG) -> FilterResult) -> ControlFlow<i32> {
+        Self::with_hook(
+            false,
+            |_, msg| filter(msg),
+            |msg_loop, filter| msg_loop.pump_loop(filter),
+        )
+    }
+
+    /// Quits the message loop as soon as possible.
+    pub fn quit(&self) {
+        self.quit.set(true);
+    }
+
+    /// Quits the message loop when there are no more messages to process.
+    pub fn quit_when_idle(&self) {
+        self.quit_when_idle_with_code(0);
+    }
+
+    /// Like [`MessageLoop::quit_when_idle`], but [`MessageLoop::run`] returns
+    /// `exit_code`.
+    ///
+    /// Tasks, which have no access to the [`MessageLoop`], can call the
+    /// `PostQuitMessage()` winapi function to the same effect.
+    pub fn quit_when_idle_with_code(&self, exit_code: i32) {
+        unsafe { PostQuitMessage(exit_code) };
+    }
+}
+
+/// Builder for a message loop with additional message processing steps.
+///
+/// ```no_run
+/// # use windows_sys::Win32::UI::WindowsAndMessaging::HACCEL;
+/// # use winmsg_executor::{FilterResult, MessageLoopConfig};
+/// # let (hwnd, haccel): (_, HACCEL) = (std::ptr::null_mut(), std::ptr::null_mut());
+/// MessageLoopConfig::new()
+///     .accelerators(hwnd, haccel)
+///     .run(|_, _| FilterResult::Forward);
+/// ```
+#[derive(Debug, Default)]
+pub struct MessageLoopConfig {
+    accelerators: Vec<(HWND, HACCEL)>,
+    dialogs: Vec<HWND>,
+    get_message_hook: bool,
+    on_message: Option<fn(&MSG)>,
+    on_poll: Option<fn(TaskId)>,
+    poll_budget: Option<usize>,
+    message_range: Option<(u32, u32)>,
+}
+
+impl MessageLoopConfig {
+    /// Creates a configuration for a plain message loop like
+    /// [`MessageLoop::run`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an accelerator table, which translates keyboard messages to
+    /// `WM_COMMAND` messages for the `hwnd` window.
+    ///
+    /// Messages forwarded by the filter closure are first offered to
+    /// `TranslateAcceleratorW()` and only dispatched if not consumed. Multiple
+    /// tables are tried in registration order.
+    ///
+    /// Accelerators are suppressed while a modal window (menu, dialog box, ...)
+    /// runs its own message loop: Those messages are only passed to the
+    /// filter closure by the `WH_MSGFILTER` hook.
+    pub fn accelerators(mut self, hwnd: HWND, haccel: HACCEL) -> Self {
+        self.accelerators.push((hwnd, haccel));
+        self
+    }
+
+    /// Registers a modeless dialog box, enabling keyboard navigation (tab,
+    /// arrow keys, ...) for its controls.
+    ///
+    /// Messages forwarded by the filter closure are offered to
+    /// `IsDialogMessageW()` after the accelerator tables. Consumed messages
+    /// are dispatched by `IsDialogMessageW()` itself. The dialog must be
+    /// registered before entering the message loop and stay valid until it
+    /// returns.
+    pub fn dialog(mut self, hwnd: HWND) -> Self {
+        self.dialogs.push(hwnd);
+        self
+    }
+
+    /// Passes messages to the filter closure with a `WH_GETMESSAGE` hook
+    /// instead of the `WH_MSGFILTER` hook.
+    ///
+    /// The filter closure then sees every message removed from the queue the
+    /// same way, whether a modal window runs its own message loop or not. Each
+    /// message is passed to the filter closure once only. Dropped messages are
+    /// replaced with `WM_NULL` before being dispatched. Messages sent with
+    /// `SendMessage()` from other threads are not seen, as they bypass the
+    /// queue.
+    pub fn get_message_hook(mut self) -> Self {
+        self.get_message_hook = true;
+        self
+    }
+
+    /// Calls `hook` for every message retrieved by the message loop, after it
+    /// was dispatched or dropped by the filter closure.
+    ///
+    /// Meant for instrumentation, e.g. to measure the message throughput.
+    /// Installed for the current thread while the loop runs, so messages of
+    /// nested loops (like [`block_on()`]) are included. Messages of modal
+    /// loops are not seen.
+    pub fn on_message(mut self, hook: fn(&MSG)) -> Self {
+        self.on_message = Some(hook);
+        self
+    }
+
+    /// Calls `hook` with the id of every task before it is polled.
+    ///
+    /// Meant for instrumentation, e.g. to count polls per task. Installed for
+    /// the current thread while the loop runs, so tasks polled by nested and
+    /// modal loops are included.
+    pub fn on_poll(mut self, hook: fn(TaskId)) -> Self {
+        self.on_poll = Some(hook);
+        self
+    }
+
+    /// Services pending input and paint messages after every `n` task polls.
+    ///
+    /// Windows retrieves posted messages, like the wake messages of tasks,
+    /// before input and paint messages. Tasks that keep waking themselves
+    /// can therefore stall the UI. With a budget, the message loop
+    /// dispatches pending input and paint messages (up to 64) once `n` tasks were
+    /// polled since the last time it did, before continuing with the next
+    /// message. Installed for the current thread while the loop runs, so
+    /// tasks polled by nested loops (like [`block_on()`]) are counted too.
+    /// Values below 1 are treated as 1.
+    ///
+    /// Has no effect with the `apc-wake` feature.
+    pub fn poll_budget(mut self, n: usize) -> Self {
+        self.poll_budget = Some(n.max(1));
+        self
+    }
+
+    /// Retrieves only messages with ids from `first` to `last` (inclusive),
+    /// like the `wMsgFilterMin` and `wMsgFilterMax` arguments of
+    /// `GetMessageW()`.
+    ///
+    /// Meant for focused nested loops, e.g. one that processes only mouse
+    /// input during a drag operation. Other messages stay queued for the
+    /// outer loop, the filter closure never sees them. Use with care: A loop
+    /// that waits for a message outside of the range never ends, and
+    /// `WM_PAINT` or `WM_TIMER` messages outside of the range are held back
+    /// until it does. Quit messages are retrieved regardless of the range
+    /// and the tasks of the thread keep running. Unlike in a loop without a
+    /// range, their wake messages are never passed to the filter closure.
+    pub fn message_range(mut self, first: u32, last: u32) -> Self {
+        self.message_range = Some((first, last));
+        self
+    }
+
+    /// Runs the message loop, see [`MessageLoop::run`].
+    pub fn run(self, filter: impl Fn(&MessageLoop, &MSG) -> FilterResult) -> Option<i32> {
+        let _hooks = InstrumentationHooks::install(self.on_message, self.on_poll);
+        let _budget = PollBudget::install(self.poll_budget);
+        MessageLoop::with_hook(
+            self.get_message_hook,
+            |msg_loop, msg| filter(msg_loop, msg),
+            |msg_loop, filter| {
+                let filter = |msg: &mut MSG| match filter(msg) {
+                    FilterResult::Forward if self.preprocess(msg) => FilterResult::Drop,
+                    filter_result => filter_result,
+                };
+                msg_loop.forward_quit(match self.message_range {
+                    Some((first, last)) => msg_loop.run_loop_in_range(filter, first, last),
+                    None => msg_loop.run_loop(filter),
+                })
+            },
+        )
+    }
+
+    // Returns `true` when the message was consumed.
+    fn preprocess(&self, msg: &MSG) -> bool {
+        self.accelerators
+            .iter()
+            .any(|&(hwnd, haccel)| unsafe { TranslateAcceleratorW(hwnd, haccel, msg) } != 0)
+            || self
+                .dialogs
+                .iter()
+                .any(|&hwnd| unsafe { IsDialogMessageW(hwnd, msg) } != 0)
+    }
+}
+
+// Restores the hooks of an outer message loop when dropped.
+struct InstrumentationHooks {
+    on_message: Option<fn(&MSG)>,
+    on_poll: Option<fn(TaskId)>,
+}
+
+impl InstrumentationHooks {
+    fn install(on_message: Option<fn(&MSG)>, on_poll: Option<fn(TaskId)>) -> Self {
+        let previous = Self {
+            on_message: MESSAGE_HOOK.get(),
+            on_poll: POLL_HOOK.get(),
+        };
+        // Hooks not set are inherited from the outer loop.
+        MESSAGE_HOOK.set(on_message.or(previous.on_message));
+        POLL_HOOK.set(on_poll.or(previous.on_poll));
+        previous
+    }
+}
+
+impl Drop for InstrumentationHooks {
+    fn drop(&mut self) {
+        MESSAGE_HOOK.set(self.on_message);
+        POLL_HOOK.set(self.on_poll);
+    }
+}
+
+// Restores the poll budget of an outer message loop when dropped.
+struct PollBudget(usize);
+
+impl PollBudget {
+    fn install(budget: Option<usize>) -> Self {
+        let previous = POLL_BUDGET.get();
+        // Inherited from the outer loop when not set.
+        POLL_BUDGET.set(budget.unwrap_or(previous));
+        POLLS_SINCE_UI.set(0);
+        Self(previous)
+    }
+}
+
+impl Drop for PollBudget {
+    fn drop(&mut self) {
+        POLL_BUDGET.set(self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CStr, rc::Rc, thread};
+
+    use super::*;
+
+    fn post_thread_message(msg: u32) {
+        unsafe { PostMessageW(ptr::null_mut(), msg, 0, 0) };
     }
 
     #[test]
@@ -275,6 +1933,39 @@ mod test {
         MessageLoop::run(|_, _| panic!());
     }
 
+    #[test]
+    fn panic_in_detached_task() {
+        drop(spawn_local(async { panic!("detached") }));
+        let panic_payload =
+            panic::catch_unwind(|| MessageLoop::run(|_, _| FilterResult::Forward)).unwrap_err();
+        assert_eq!(panic_payload.downcast_ref::<&str>(), Some(&"detached"));
+    }
+
+    #[test]
+    fn join_handle_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let task = spawn_local(async { 42 });
+        assert_send(&task);
+        // Runs on this thread, only the handle moves.
+        block_on(yield_now());
+        assert!(task.is_finished());
+        let output = thread::scope(|s| s.spawn(|| block_on(task)).join().unwrap());
+        assert_eq!(output.unwrap(), 42);
+    }
+
+    #[test]
+    fn panic_in_finished_task_dropped() {
+        let task = spawn_local(async { panic!("finished") });
+        block_on(yield_now());
+        assert!(task.is_finished());
+        drop(task);
+
+        post_thread_message(WM_USER);
+        let panic_payload =
+            panic::catch_unwind(|| MessageLoop::run(|_, _| FilterResult::Forward)).unwrap_err();
+        assert_eq!(panic_payload.downcast_ref::<&str>(), Some(&"finished"));
+    }
+
     #[test]
     fn message_loop_quit() {
         for i in 0..10 {
@@ -289,6 +1980,259 @@ mod test {
         });
     }
 
+    #[test]
+    fn block_on_woken_before_quit() {
+        let mut woken = false;
+        let output = block_on(poll_fn(|cx| {
+            if woken {
+                return Poll::Ready(1);
+            }
+            woken = true;
+            // Received by the loop before the wake message.
+            unsafe { PostMessageW(ptr::null_mut(), WM_QUIT, 7, 0) };
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }));
+        assert_eq!(output, 1);
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(7));
+    }
+
+    #[test]
+    fn block_on_quit_without_result() {
+        panic::catch_unwind(|| {
+            block_on(async {
+                unsafe { PostQuitMessage(0) };
+                std::future::pending::<()>().await;
+            })
+        })
+        .unwrap_err();
+        // Not posted again for an outer loop.
+        let mut msg = MaybeUninit::uninit();
+        let quit = unsafe {
+            PeekMessageW(
+                msg.as_mut_ptr(),
+                ptr::null_mut(),
+                WM_QUIT,
+                WM_QUIT,
+                PM_REMOVE,
+            )
+        };
+        assert_eq!(quit, 0);
+    }
+
+    #[test]
+    fn block_on_drains_woken_tasks() {
+        let ran = Rc::new(Cell::new(false));
+        let spawn_child = || {
+            let ran = ran.clone();
+            spawn_local(async move { ran.set(true) }).detach();
+        };
+        block_on(async { spawn_child() });
+        assert!(!ran.get());
+        block_on(yield_now());
+        assert!(ran.get());
+
+        ran.set(false);
+        BlockOnConfig::new()
+            .drain_woken_tasks()
+            .block_on(async { spawn_child() });
+        assert!(ran.get());
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn drain_polls_yielding_task_once() {
+        let polls = Rc::new(Cell::new(0));
+        let task = spawn_local({
+            let polls = polls.clone();
+            async move {
+                loop {
+                    polls.set(polls.get() + 1);
+                    yield_now().await;
+                }
+            }
+        });
+        BlockOnConfig::new().drain_woken_tasks().block_on(async {});
+        // Polled once before the future completed and once by the drain.
+        assert_eq!(polls.get(), 2);
+        task.abort();
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn wake_after_executor_window_destroyed() {
+        struct SetOnDrop(Rc<Cell<bool>>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        // Own thread, the executor window is gone afterwards.
+        thread::spawn(|| {
+            let dropped = Rc::new(Cell::new(false));
+            let waker = Rc::new(RefCell::new(None));
+            let guard = SetOnDrop(dropped.clone());
+            let task = spawn_local({
+                let waker = waker.clone();
+                poll_fn(move |cx| {
+                    let _guard = &guard;
+                    *waker.borrow_mut() = Some(cx.waker().clone());
+                    Poll::<()>::Pending
+                })
+            });
+            block_on(yield_now());
+            drop(task);
+
+            destroy_executor_window();
+            waker.take().unwrap().wake();
+            assert!(dropped.get());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn remote_wake_after_executor_window_destroyed() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct SetOnDrop(Arc<AtomicBool>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        thread::spawn({
+            let dropped = dropped.clone();
+            move || {
+                let waker = Rc::new(RefCell::new(None));
+                let guard = SetOnDrop(dropped.clone());
+                let task = spawn_local({
+                    let waker = waker.clone();
+                    poll_fn(move |cx| {
+                        let _guard = &guard;
+                        *waker.borrow_mut() = Some(cx.waker().clone());
+                        Poll::<()>::Pending
+                    })
+                });
+                block_on(yield_now());
+                drop(task);
+
+                destroy_executor_window();
+                // Cannot drop the future on another thread, it is leaked.
+                let waker: Waker = waker.take().unwrap();
+                thread::spawn(move || waker.wake()).join().unwrap();
+                assert!(!dropped.load(Ordering::Relaxed));
+            }
+        })
+        .join()
+        .unwrap();
+        assert!(!dropped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn queued_wakes_dropped_on_thread_exit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct CountDrop(Arc<AtomicUsize>);
+
+        impl Drop for CountDrop {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let guard = CountDrop(dropped.clone());
+        thread::spawn(move || {
+            // Never polled, the wake message of the first poll stays queued.
+            spawn_local(async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
+            })
+            .detach();
+        })
+        .join()
+        .unwrap();
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn on_done_callbacks() {
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let on_done = |id| {
+            let results = results.clone();
+            move |result| results.borrow_mut().push((id, result))
+        };
+        let completed = spawn_with_on_done(async { 1 }, on_done(0));
+        let aborted = spawn_with_on_done(std::future::pending::<()>(), on_done(1));
+        let panicked = spawn_with_on_done(async { panic!() }, on_done(2));
+        aborted.abort();
+        block_on(async {
+            assert_eq!(completed.await.unwrap(), 1);
+            assert!(panicked.await.unwrap_err().is_panic());
+        });
+        results.borrow_mut().sort_by_key(|&(id, _)| id);
+        assert_eq!(
+            *results.borrow(),
+            [
+                (0, TaskResult::Completed),
+                (1, TaskResult::Aborted),
+                (2, TaskResult::Panicked),
+            ]
+        );
+    }
+
+    #[test]
+    fn try_spawn() {
+        let task = try_spawn_local(async { 42 }).unwrap();
+        assert_eq!(block_on(task).unwrap(), 42);
+    }
+
+    #[test]
+    fn message_info() {
+        post_thread_message(WM_USER);
+        MessageLoop::run(|msg_loop, msg| {
+            let info = MessageInfo::from(msg);
+            assert_eq!(info, MessageInfo::current());
+            assert!(info.age() < Duration::from_secs(10));
+            msg_loop.quit();
+            FilterResult::Drop
+        });
+    }
+
+    #[test]
+    fn nested_loops_forward_quit() {
+        post_thread_message(WM_USER);
+        let inner_exit_code = Cell::new(None);
+        let exit_code = MessageLoop::run(|_, msg| {
+            if msg.message == WM_USER {
+                unsafe { PostQuitMessage(3) };
+                inner_exit_code.set(MessageLoop::run(|_, _| FilterResult::Forward));
+            }
+            FilterResult::Forward
+        });
+        assert_eq!(inner_exit_code.get(), Some(3));
+        assert_eq!(exit_code, Some(3));
+
+        // A nested `block_on()` completes its future before quitting.
+        let task = spawn_local(async {
+            block_on(async {
+                unsafe { PostQuitMessage(5) };
+                yield_now().await;
+                42
+            })
+        });
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(5));
+        assert_eq!(task.now_or_never().unwrap().unwrap(), 42);
+    }
+
     #[test]
     fn message_loop_quit_when_idle() {
         for i in 0..10 {
@@ -304,6 +2248,298 @@ mod test {
         assert_eq!(expected_msg.get(), 10);
     }
 
+    #[test]
+    fn message_loop_idle() {
+        let task = spawn_local(async {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+        });
+        let idle_count = Cell::new(0);
+        MessageLoop::run_with_idle(
+            |_, _| FilterResult::Forward,
+            |msg_loop| {
+                idle_count.set(idle_count.get() + 1);
+                if task.is_finished() {
+                    msg_loop.quit();
+                }
+            },
+        );
+        assert!(idle_count.get() >= 1);
+    }
+
+    #[test]
+    fn pump_messages() {
+        let task = spawn_local(async {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+        });
+        let pump = || MessageLoop::pump_messages(|_| FilterResult::Forward);
+        assert_eq!(pump(), ControlFlow::Continue(()));
+        assert!(task.is_finished());
+
+        unsafe { PostQuitMessage(5) };
+        assert_eq!(pump(), ControlFlow::Break(5));
+    }
+
+    #[test]
+    fn filter_quit() {
+        let w = Window::new(WindowType::MessageOnly, Cell::new(0), |received, msg| {
+            (msg.msg == WM_USER).then(|| {
+                received.set(received.get() + 1);
+                0
+            })
+        })
+        .unwrap();
+        let quit_filter = |msg: &MSG| {
+            if msg.hwnd == w.hwnd() && msg.wParam == 1 {
+                FilterResult::Quit
+            } else {
+                FilterResult::Forward
+            }
+        };
+
+        for wparam in [0, 1, 0] {
+            unsafe { w.post(WM_USER, wparam, 0).unwrap() };
+        }
+        assert_eq!(MessageLoop::run(|_, msg| quit_filter(msg)), None);
+        // The quit message was not dispatched, the next one still queued.
+        assert_eq!(w.state().get(), 1);
+
+        unsafe { w.post(WM_USER, 1, 0).unwrap() };
+        assert_eq!(
+            MessageLoop::pump_messages(quit_filter),
+            ControlFlow::Break(0)
+        );
+        assert_eq!(w.state().get(), 2);
+    }
+
+    #[test]
+    fn translate_accelerator() {
+        const VK_F5: u16 = 0x74;
+        const CMD_ID: u16 = 42;
+        let accel = ACCEL {
+            fVirt: FVIRTKEY,
+            key: VK_F5,
+            cmd: CMD_ID,
+        };
+        let haccel = unsafe { CreateAcceleratorTableA(&accel, 1) };
+        assert!(!haccel.is_null());
+
+        // Records `WM_COMMAND` ids and `WM_KEYDOWN` key codes.
+        let w = Window::new(WindowType::MessageOnly, Cell::new(None), |received, msg| {
+            match msg.msg {
+                WM_COMMAND => received.set(Some(msg.wparam & 0xFFFF)),
+                WM_KEYDOWN => received.set(Some(msg.wparam)),
+                _ => return None,
+            }
+            Some(0)
+        })
+        .unwrap();
+        unsafe { w.post(WM_KEYDOWN, VK_F5 as _, 0).unwrap() };
+
+        MessageLoopConfig::new()
+            .accelerators(w.hwnd(), haccel)
+            .run(|msg_loop, msg| {
+                if msg.message == WM_KEYDOWN {
+                    msg_loop.quit();
+                }
+                FilterResult::Forward
+            });
+        // The key down message was consumed by the accelerator.
+        assert_eq!(w.state().get(), Some(CMD_ID as usize));
+
+        unsafe { DestroyAcceleratorTable(haccel) };
+    }
+
+    #[test]
+    fn dialog_message() {
+        let w = Window::new(WindowType::MessageOnly, Cell::new(0), |count, msg| {
+            if msg.msg == WM_USER {
+                count.set(count.get() + 1);
+            }
+            None
+        })
+        .unwrap();
+        unsafe { w.post(WM_USER, 0, 0).unwrap() };
+
+        MessageLoopConfig::new()
+            .dialog(w.hwnd())
+            .run(|msg_loop, msg| {
+                if msg.message == WM_USER {
+                    msg_loop.quit();
+                }
+                FilterResult::Forward
+            });
+        // Dispatched by `IsDialogMessageW()` only, not a second time.
+        assert_eq!(w.state().get(), 1);
+    }
+
+    #[test]
+    fn modify_message() {
+        let w = Window::new(WindowType::MessageOnly, Cell::new(0), |wparam, msg| {
+            if msg.msg == WM_USER {
+                wparam.set(msg.wparam);
+            }
+            None
+        })
+        .unwrap();
+        unsafe { w.post(WM_USER, 1, 0).unwrap() };
+
+        MessageLoop::run_mut(|msg_loop, msg| {
+            if msg.message == WM_USER {
+                msg.wParam = 2;
+                msg_loop.quit();
+            }
+            FilterResult::Forward
+        });
+        assert_eq!(w.state().get(), 2);
+    }
+
+    #[test]
+    fn get_message_hook() {
+        let w = Window::new(WindowType::MessageOnly, Cell::new(0), |count, msg| {
+            if msg.msg == WM_USER {
+                count.set(count.get() + 1);
+            }
+            None
+        })
+        .unwrap();
+        for _ in 0..3 {
+            unsafe { w.post(WM_USER, 0, 0).unwrap() };
+        }
+        unsafe { w.post(WM_USER + 1, 0, 0).unwrap() };
+
+        let filtered = Cell::new(0);
+        MessageLoopConfig::new()
+            .get_message_hook()
+            .run(|msg_loop, msg| {
+                if msg.message == WM_USER {
+                    filtered.set(filtered.get() + 1);
+                    return FilterResult::Drop;
+                }
+                if msg.message == WM_USER + 1 {
+                    msg_loop.quit();
+                }
+                FilterResult::Forward
+            });
+        // Filtered once per message and dropped messages are not dispatched.
+        assert_eq!(filtered.get(), 3);
+        assert_eq!(w.state().get(), 0);
+    }
+
+    #[test]
+    fn round_robin_scheduling() {
+        for batch_size in [1, 4] {
+            set_wake_batch_size(batch_size);
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let tasks: Vec<_> = (0..3)
+                .map(|i| {
+                    let log = log.clone();
+                    spawn_local(async move {
+                        for _ in 0..3 {
+                            log.borrow_mut().push(i);
+                            yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+            block_on(async {
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            });
+            assert_eq!(*log.borrow(), [0, 1, 2, 0, 1, 2, 0, 1, 2]);
+        }
+        set_wake_batch_size(1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn batch_wake_messages() {
+        set_wake_batch_size(4);
+        let tasks: Vec<_> = (0..8).map(|_| spawn_local(async {})).collect();
+        let wake_messages = Cell::new(0);
+        MessageLoop::run(|msg_loop, msg| {
+            if msg.message == msg_id_wake() {
+                wake_messages.set(wake_messages.get() + 1);
+            }
+            msg_loop.quit_when_idle();
+            FilterResult::Forward
+        });
+        set_wake_batch_size(1);
+
+        assert!(tasks.iter().all(JoinHandle::is_finished));
+        assert_eq!(wake_messages.get(), 2);
+
+        // A self-waking task ends the batch, the posted message comes next.
+        set_wake_batch_size(4);
+        let polls = Rc::new(Cell::new(0));
+        let task = spawn_local({
+            let polls = polls.clone();
+            async move {
+                loop {
+                    polls.set(polls.get() + 1);
+                    yield_now().await;
+                }
+            }
+        });
+        post_thread_message(WM_USER);
+        MessageLoop::run(|_, msg| {
+            if msg.message == WM_USER {
+                FilterResult::Quit
+            } else {
+                FilterResult::Forward
+            }
+        });
+        set_wake_batch_size(1);
+        assert_eq!(polls.get(), 1);
+        task.abort();
+    }
+
+    #[test]
+    fn block_on_with_timeout() {
+        let long_sleep = || timer::sleep(Duration::from_secs(60));
+        assert_eq!(
+            block_on_timeout(async { 42 }, Duration::from_secs(60), OnTimeout::Abort),
+            Ok(42)
+        );
+        assert_eq!(
+            block_on_timeout(long_sleep(), Duration::from_millis(10), OnTimeout::Abort),
+            Err(timer::Elapsed)
+        );
+
+        let alive = Rc::new(());
+        let future = {
+            let alive = alive.clone();
+            async move {
+                let _alive = alive;
+                long_sleep().await;
+            }
+        };
+        let result = block_on_timeout(future, Duration::from_millis(10), OnTimeout::Detach);
+        assert_eq!(result, Err(timer::Elapsed));
+        assert_eq!(Rc::strong_count(&alive), 2);
+    }
+
+    #[test]
+    fn message_loop_exit_code() {
+        post_thread_message(WM_USER);
+        let exit_code = MessageLoop::run(|msg_loop, _| {
+            msg_loop.quit_when_idle_with_code(42);
+            FilterResult::Drop
+        });
+        assert_eq!(exit_code, Some(42));
+
+        post_thread_message(WM_USER);
+        let exit_code = MessageLoop::run(|msg_loop, _| {
+            msg_loop.quit();
+            FilterResult::Drop
+        });
+        assert_eq!(exit_code, None);
+    }
+
     #[test]
     fn nested_block_on() {
         let count: Cell<usize> = Cell::new(0);
@@ -325,27 +2561,34 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn nested_message_loop() {
         post_thread_message(WM_USER);
-        MessageLoop::run(|_, _| {
-            MessageLoop::run(|_, _| FilterResult::Drop);
+        MessageLoop::run(|outer_loop, msg| {
+            if msg.message == WM_USER {
+                post_thread_message(WM_USER + 1);
+                MessageLoop::run(|inner_loop, msg| {
+                    assert_eq!(msg.message, WM_USER + 1);
+                    inner_loop.quit();
+                    FilterResult::Drop
+                });
+                outer_loop.quit();
+            }
             FilterResult::Drop
         });
     }
 
-    async fn yield_now() {
-        let mut yielded = false;
-        poll_fn(|cx| {
-            if yielded {
-                Poll::Ready(())
-            } else {
-                yielded = true;
-                cx.waker().wake_by_ref();
-                Poll::Pending
-            }
-        })
-        .await;
+    #[test]
+    fn yield_to_other_task() {
+        let other_executed = Rc::new(Cell::new(false));
+        block_on(async {
+            drop(spawn_local({
+                let other_executed = other_executed.clone();
+                async move { other_executed.set(true) }
+            }));
+            assert!(!other_executed.get());
+            yield_now().await;
+            assert!(other_executed.get());
+        });
     }
 
     #[test]
@@ -375,6 +2618,435 @@ mod test {
         });
     }
 
+    #[test]
+    fn abort_task() {
+        let alive = Rc::new(());
+        let task = spawn_local({
+            let alive = alive.clone();
+            async move {
+                let _alive = alive;
+                poll_fn(|_| Poll::<()>::Pending).await;
+            }
+        });
+
+        block_on(yield_now());
+        assert_eq!(Rc::strong_count(&alive), 2);
+        task.abort();
+        block_on(yield_now());
+        assert_eq!(Rc::strong_count(&alive), 1);
+    }
+
+    #[test]
+    fn task_is_finished() {
+        let task = spawn_local(async {});
+        assert!(!task.is_finished());
+        block_on(yield_now());
+        assert!(task.is_finished());
+
+        let task = spawn_local(async {});
+        task.abort();
+        assert!(task.is_finished());
+    }
+
+    #[test]
+    fn await_aborted_task() {
+        let task = spawn_local(async {});
+        task.abort();
+        assert!(block_on(task).unwrap_err().is_aborted());
+    }
+
+    #[test]
+    fn await_panicked_task() {
+        let task = spawn_local(async { panic!() });
+        assert!(block_on(task).unwrap_err().is_panic());
+    }
+
+    #[test]
+    #[cfg(feature = "task-count")]
+    fn count_tasks() {
+        assert_eq!(task_count(), 0);
+        block_on(async {
+            assert_eq!(task_count(), 1);
+            let task = spawn_local(yield_now());
+            let aborted = spawn_local(yield_now());
+            assert_eq!(task_count(), 3);
+            // The executor drops the future of an aborted task when it
+            // processes the wake message sent on abort.
+            aborted.abort();
+            task.await.unwrap();
+            assert_eq!(task_count(), 1);
+        });
+        assert_eq!(task_count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "task-count")]
+    fn shutdown_drains_tasks() {
+        let done = Rc::new(Cell::new(0));
+        block_on(async {
+            for _ in 0..2 {
+                let done = done.clone();
+                spawn_local(async move {
+                    timer::sleep(Duration::from_millis(20)).await;
+                    // Not accepted during shutdown.
+                    assert!(spawn_local(async {}).await.is_err());
+                    done.set(done.get() + 1);
+                });
+            }
+            shutdown(None).await.unwrap();
+            assert_eq!(task_count(), 1);
+        });
+        assert_eq!(done.get(), 2);
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(0));
+    }
+
+    #[test]
+    #[cfg(feature = "task-count")]
+    #[should_panic(expected = "called while the thread shuts down")]
+    fn block_on_timeout_during_shutdown() {
+        spawn_local(async {
+            // Polled again once the shutdown started.
+            yield_now().await;
+            let _ = block_on_timeout(async {}, Duration::from_secs(60), OnTimeout::Abort);
+        })
+        .detach();
+        block_on(shutdown(None)).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "task-count")]
+    fn shutdown_timeout() {
+        block_on(async {
+            spawn_local(timer::sleep(Duration::from_secs(60)));
+            let result = shutdown(Some(Duration::from_millis(10))).await;
+            assert_eq!(result, Err(timer::Elapsed));
+            // Accepts new tasks again.
+            spawn_local(async {}).await.unwrap();
+        });
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(0));
+    }
+
+    #[test]
+    #[cfg(feature = "apc-wake")]
+    fn apc_wake_from_other_thread() {
+        let value = block_on(async {
+            let (tx, rx) = util::oneshot();
+            thread::spawn(move || tx.send(42).unwrap());
+            rx.await.unwrap()
+        });
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    #[cfg(feature = "futures-spawn")]
+    fn futures_spawn_traits() {
+        use futures_task::{LocalSpawn, Spawn};
+
+        let done = Rc::new(Cell::new(0));
+        let spawner = local_spawner();
+        let local_done = done.clone();
+        let future = async move { local_done.set(1) };
+        spawner
+            .spawn_local_obj(LocalFutureObj::new(Box::new(future)))
+            .unwrap();
+
+        let (tx, rx) = util::oneshot();
+        let remote = remote_handle();
+        thread::spawn(move || {
+            let future = async move { tx.send(2).unwrap() };
+            remote.spawn_obj(FutureObj::new(Box::new(future))).unwrap();
+        })
+        .join()
+        .unwrap();
+
+        block_on(async {
+            assert_eq!(rx.await.unwrap(), 2);
+            yield_now().await;
+        });
+        assert_eq!(done.get(), 1);
+    }
+
+    #[test]
+    fn foreign_wake_messages_ignored() {
+        let hwnd = remote_handle().hwnd;
+        for msg in [msg_id_wake(), msg_id_spawn()] {
+            unsafe { PostMessageW(hwnd, msg, 0, 1) };
+        }
+        let task = spawn_local(async { 42 });
+        assert_eq!(block_on(task).unwrap(), 42);
+        run_pending_tasks();
+    }
+
+    #[test]
+    fn run_only_pending_tasks() {
+        let task = spawn_local(async {
+            yield_now().await;
+            42
+        });
+        post_thread_message(WM_USER);
+        run_pending_tasks();
+        assert_eq!(task.now_or_never().unwrap().unwrap(), 42);
+
+        // Other messages stay queued.
+        let msg = peek_message().unwrap();
+        assert_eq!(msg.message, WM_USER);
+    }
+
+    #[test]
+    fn detached_task_runs() {
+        let done = Rc::new(Cell::new(false));
+        spawn_local({
+            let done = done.clone();
+            async move { done.set(true) }
+        })
+        .detach();
+        block_on(yield_now());
+        assert!(done.get());
+    }
+
+    #[test]
+    fn join_handle_now_or_never() {
+        let task = spawn_local(async { 42 });
+        block_on(yield_now());
+        assert_eq!(task.now_or_never().unwrap().unwrap(), 42);
+
+        // Detached when not finished.
+        let done = Rc::new(Cell::new(false));
+        let task = spawn_local({
+            let done = done.clone();
+            async move { done.set(true) }
+        });
+        assert!(task.now_or_never().is_none());
+        block_on(yield_now());
+        assert!(done.get());
+    }
+
+    #[test]
+    fn instrumentation_hooks() {
+        thread_local! {
+            static MESSAGES: Cell<usize> = const { Cell::new(0) };
+            static POLLED: RefCell<Vec<TaskId>> = const { RefCell::new(Vec::new()) };
+        }
+
+        let task = spawn_local(yield_now());
+        let id = task.id();
+        assert_ne!(spawn_local(async {}).id(), id);
+        post_thread_message(WM_USER);
+        MessageLoopConfig::new()
+            .on_message(|_| MESSAGES.set(MESSAGES.get() + 1))
+            .on_poll(|id| POLLED.with_borrow_mut(|polled| polled.push(id)))
+            .run(|msg_loop, msg| {
+                if msg.message == WM_USER {
+                    msg_loop.quit();
+                }
+                FilterResult::Forward
+            });
+        // Wake messages of both tasks and `WM_USER`.
+        #[cfg(not(feature = "apc-wake"))]
+        assert_eq!(MESSAGES.get(), 3);
+        assert_eq!(POLLED.with_borrow(|polled| polled[0]), id);
+
+        // Uninstalled after the loop returned.
+        let messages = MESSAGES.get();
+        block_on(task).unwrap();
+        assert_eq!(MESSAGES.get(), messages);
+    }
+
+    #[test]
+    fn message_range_loop() {
+        post_thread_message(WM_USER + 1);
+        // Woken tasks keep running.
+        spawn_local(async {
+            yield_now().await;
+            post_thread_message(WM_USER);
+        })
+        .detach();
+        let exit_code = MessageLoopConfig::new()
+            .message_range(WM_USER, WM_USER)
+            .run(|_, msg| {
+                assert_eq!(msg.message, WM_USER);
+                FilterResult::Quit
+            });
+        assert_eq!(exit_code, None);
+        assert_eq!(peek_message().unwrap().message, WM_USER + 1);
+
+        unsafe { PostQuitMessage(3) };
+        let exit_code = MessageLoopConfig::new()
+            .message_range(WM_USER, WM_USER)
+            .run(|_, _| FilterResult::Forward);
+        assert_eq!(exit_code, Some(3));
+    }
+
+    #[test]
+    fn message_range_hides_wakes() {
+        spawn_local(async {
+            for _ in 0..10 {
+                yield_now().await;
+            }
+            post_thread_message(WM_USER);
+        })
+        .detach();
+        // Includes the ids of registered messages.
+        let exit_code = MessageLoopConfig::new()
+            .message_range(WM_USER, 0xFFFF)
+            .run(|_, msg| {
+                assert_eq!(msg.message, WM_USER);
+                FilterResult::Quit
+            });
+        assert_eq!(exit_code, None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn yield_services_paint() {
+        use windows_sys::Win32::Graphics::Gdi::InvalidateRect;
+
+        let w = util::WindowBuilder::new(WindowType::TopLevel)
+            .style(WS_POPUP | WS_VISIBLE)
+            .ex_style(WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE)
+            .rect(0, 0, 1, 1)
+            .build(Cell::new(false), |painted, msg| {
+                if msg.msg == WM_PAINT {
+                    painted.set(true);
+                }
+                None
+            })
+            .unwrap();
+        unsafe { InvalidateRect(w.hwnd(), ptr::null(), 0) };
+
+        let polls = block_on(async {
+            let mut polls = 1;
+            while !w.state().get() && polls < 100 {
+                yield_now().await;
+                polls += 1;
+            }
+            polls
+        });
+        // Painted right after the first yield, without a timer round trip.
+        assert_eq!(polls, 2);
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn yield_with_unvalidated_paint() {
+        use windows_sys::Win32::Graphics::Gdi::InvalidateRect;
+
+        let w = util::WindowBuilder::new(WindowType::TopLevel)
+            .style(WS_POPUP | WS_VISIBLE)
+            .ex_style(WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE)
+            .rect(0, 0, 1, 1)
+            .build(Cell::new(0), |paints, msg| {
+                // Handled without `BeginPaint()`, the window stays invalid.
+                (msg.msg == WM_PAINT).then(|| {
+                    paints.set(paints.get() + 1);
+                    0
+                })
+            })
+            .unwrap();
+        unsafe { InvalidateRect(w.hwnd(), ptr::null(), 0) };
+
+        // Resumes once the drain gives up.
+        block_on(yield_now());
+        assert!(w.state().get() >= UI_DRAIN_LIMIT);
+    }
+
+    #[test]
+    #[cfg(not(feature = "apc-wake"))]
+    fn poll_budget_services_paint() {
+        use windows_sys::Win32::Graphics::Gdi::InvalidateRect;
+
+        let w = util::WindowBuilder::new(WindowType::TopLevel)
+            .style(WS_POPUP | WS_VISIBLE)
+            .ex_style(WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE)
+            .rect(0, 0, 1, 1)
+            .build(Rc::new(Cell::new(false)), |painted, msg| {
+                if msg.msg == WM_PAINT {
+                    painted.set(true);
+                }
+                None
+            })
+            .unwrap();
+        unsafe { InvalidateRect(w.hwnd(), ptr::null(), 0) };
+
+        let painted = Rc::clone(&w.state());
+        let task = spawn_local(async move {
+            let mut polls = 0;
+            poll_fn(|cx| {
+                polls += 1;
+                if painted.get() || polls == 100 {
+                    Poll::Ready(())
+                } else {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            post_thread_message(WM_USER);
+            polls
+        });
+        MessageLoopConfig::new()
+            .poll_budget(4)
+            .run(|msg_loop, msg| {
+                if msg.message == WM_USER {
+                    msg_loop.quit();
+                }
+                FilterResult::Forward
+            });
+        assert!(w.state().get());
+        assert!(task.now_or_never().unwrap().unwrap() <= 5);
+    }
+
+    #[test]
+    fn named_task() {
+        block_on(async {
+            let task = spawn_named("worker", async { 42 });
+            assert_eq!(task.name(), Some("worker"));
+            assert_eq!(task.await.unwrap(), 42);
+            assert_eq!(spawn_local(async {}).name(), None);
+
+            let err = spawn_named("worker", async { panic!() }).await.unwrap_err();
+            assert_eq!(format!("{err:?}"), r#"JoinError::Panic("worker", ..)"#);
+            assert_eq!(err.to_string(), r#"task "worker" panicked"#);
+        });
+    }
+
+    #[test]
+    fn await_sync_task_from_other_thread() {
+        block_on(async {
+            let handle = spawn_sync(async {
+                yield_now().await;
+                42
+            });
+            let (sender, receiver) = oneshot();
+            let waiter = thread::spawn(move || sender.send(block_on(handle).unwrap()));
+            assert_eq!(receiver.await, Ok(42));
+            waiter.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn spawn_from_other_thread() {
+        let executor_thread = thread::current().id();
+        let remote = remote_handle();
+        thread::spawn(move || {
+            remote
+                .spawn_on(move || {
+                    // Futures spawned remotely do not need to be `Send`.
+                    let not_send = Rc::new(());
+                    async move {
+                        let _not_send = not_send;
+                        assert_eq!(thread::current().id(), executor_thread);
+                        unsafe { PostQuitMessage(0) };
+                    }
+                })
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+
+        MessageLoop::run(|_, _| FilterResult::Forward);
+    }
+
     fn window_by_name(name: &CStr) -> HWND {
         unsafe { FindWindowA(ptr::null_mut(), name.as_ptr() as _) }
     }
@@ -398,7 +3070,7 @@ mod test {
 
             // Close the modal window.
             unsafe {
-                SendMessageA(window_by_name(window_name), WM_CLOSE, 0, 0);
+                SendMessageW(window_by_name(window_name), WM_CLOSE, 0, 0);
             }
         });
 
@@ -411,7 +3083,7 @@ mod test {
                     0,
                 );
             }
-            task.await;
+            task.await.unwrap();
         });
     }
 
@@ -504,7 +3176,7 @@ mod test {
             }
 
             // Close modal window again.
-            unsafe { SendMessageA(window_by_name(window_name), WM_CLOSE, 0, 0) };
+            unsafe { SendMessageW(window_by_name(window_name), WM_CLOSE, 0, 0) };
         });
 
         let expected_msg = Cell::new(0);
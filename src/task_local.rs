@@ -0,0 +1,191 @@
+//! Task-local storage, see [`task_local!`](crate::task_local!).
+
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt, ptr,
+};
+
+/// Declares task-local keys of type [`LocalKey`].
+///
+/// The syntax mirrors [`thread_local!`]. Each task spawned with
+/// [`spawn_local()`](crate::spawn_local) (or run by [`block_on()`](crate::block_on))
+/// gets its own copy of the value, lazily initialized on first access. A task
+/// spawned from within another task does not inherit the value of its parent.
+///
+/// ```
+/// use std::cell::Cell;
+///
+/// winmsg_executor::task_local! {
+///     static REQUEST_ID: Cell<u32> = Cell::new(0);
+/// }
+///
+/// winmsg_executor::block_on(async {
+///     REQUEST_ID.with(|id| id.set(42));
+///     let task = winmsg_executor::spawn_local(async { REQUEST_ID.with(Cell::get) });
+///     assert_eq!(task.await.unwrap(), 0);
+///     assert_eq!(REQUEST_ID.with(Cell::get), 42);
+/// });
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::task_local::LocalKey::new(__init)
+        };
+    };
+}
+
+/// Key for task-local data, declared with [`task_local!`](crate::task_local!).
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+/// Error returned by [`LocalKey::try_with`] when called outside of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task-local value accessed outside of a task")
+    }
+}
+
+impl Error for AccessError {}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Acquires a reference to the value of the currently polled task.
+    ///
+    /// # Panics
+    ///
+    /// Panics when not called from within a task.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f).unwrap()
+    }
+
+    /// Acquires a reference to the value of the currently polled task.
+    ///
+    /// Returns [`AccessError`] when not called from within a task.
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let locals = CURRENT_TASK_LOCALS.get();
+        if locals.is_null() {
+            return Err(AccessError);
+        }
+        // SAFETY: The pointer is reset before the task is done polling.
+        let locals = unsafe { &*locals };
+        let key = ptr::from_ref(self) as usize;
+
+        let existing = locals.get(key);
+        let value = existing.unwrap_or_else(|| {
+            // Initialize outside of the borrow, `init` may access other keys.
+            let value: Box<dyn Any> = Box::new((self.init)());
+            locals.insert(key, value)
+        });
+        // SAFETY: Values are boxed and never removed while the task is alive,
+        // so the reference stays valid during the current poll.
+        Ok(f(unsafe { &*value.cast::<T>() }))
+    }
+}
+
+/// Values of the task-local keys accessed by a task, stored alongside the
+/// task future.
+#[derive(Default)]
+pub(crate) struct TaskLocals {
+    values: RefCell<Vec<(usize, Box<dyn Any>)>>,
+}
+
+impl TaskLocals {
+    fn get(&self, key: usize) -> Option<*const dyn Any> {
+        let values = self.values.borrow();
+        let (_, value) = values.iter().find(|(k, _)| *k == key)?;
+        Some(ptr::from_ref(&**value))
+    }
+
+    fn insert(&self, key: usize, value: Box<dyn Any>) -> *const dyn Any {
+        let value_ptr = ptr::from_ref(&*value);
+        self.values.borrow_mut().push((key, value));
+        value_ptr
+    }
+}
+
+thread_local! {
+    static CURRENT_TASK_LOCALS: Cell<*const TaskLocals> = const { Cell::new(ptr::null()) };
+}
+
+/// Makes `locals` accessible until the returned guard is dropped.
+pub(crate) fn enter(locals: &TaskLocals) -> impl Drop + '_ {
+    struct Guard<'a> {
+        previous: *const TaskLocals,
+        _locals: &'a TaskLocals,
+    }
+
+    impl Drop for Guard<'_> {
+        fn drop(&mut self) {
+            // Restore the outer task's locals for nested message loops.
+            CURRENT_TASK_LOCALS.set(self.previous);
+        }
+    }
+
+    Guard {
+        previous: CURRENT_TASK_LOCALS.replace(locals),
+        _locals: locals,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::{block_on, spawn_local};
+
+    task_local! {
+        static VALUE: Cell<u32> = Cell::new(0);
+        static OTHER: u32 = VALUE.with(Cell::get) + 1
+    }
+
+    #[test]
+    fn access_outside_task() {
+        assert!(VALUE.try_with(|_| ()).is_err());
+    }
+
+    #[test]
+    fn value_per_task() {
+        block_on(async {
+            VALUE.with(|v| v.set(42));
+            let task = spawn_local(async {
+                assert_eq!(VALUE.with(Cell::get), 0);
+                VALUE.with(|v| v.set(1));
+                crate::yield_now().await;
+                VALUE.with(Cell::get)
+            });
+            assert_eq!(task.await.unwrap(), 1);
+
+            // Nested message loop polls its own future.
+            block_on(async { assert_eq!(VALUE.with(Cell::get), 0) });
+            assert_eq!(VALUE.with(Cell::get), 42);
+        });
+    }
+
+    #[test]
+    fn init_accesses_other_key() {
+        block_on(async {
+            VALUE.with(|v| v.set(2));
+            assert_eq!(OTHER.with(|v| *v), 3);
+        });
+    }
+}
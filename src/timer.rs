@@ -0,0 +1,739 @@
+//! Timers driven by `WM_TIMER` messages.
+//!
+//! All timers of a thread share one hidden message-only window. The timer
+//! messages are dispatched by the message loop like any other message, no
+//! additional thread is required.
+//!
+//! # Accuracy
+//!
+//! Timers are based on [`SetTimer()`] and inherit its limitations: The
+//! resolution is bound to the system timer tick (typically around 15.6ms) and
+//! `WM_TIMER` messages are only generated when the message queue is empty.
+//! Expect timers to fire late, sometimes considerably when the thread is busy.
+//! Use [`sleep_precise()`] when a shorter delay matters.
+//!
+//! [`SetTimer()`]: https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-settimer
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    future::{poll_fn, Future},
+    marker::PhantomData,
+    mem,
+    pin::{pin, Pin},
+    ptr,
+    rc::{Rc, Weak},
+    task::{ready, Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    Foundation::*,
+    System::Threading::{
+        CreateWaitableTimerExW, SetWaitableTimer, CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+        TIMER_ALL_ACCESS,
+    },
+    UI::WindowsAndMessaging::*,
+};
+
+use crate::{
+    spawn_local,
+    util::{wait_for_handle, Window, WindowType},
+    JoinHandle,
+};
+
+#[derive(Default)]
+struct TimerState {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+type Timers = RefCell<HashMap<usize, TimerState>>;
+
+fn with_timers<R>(f: impl FnOnce(HWND, &mut HashMap<usize, TimerState>) -> R) -> R {
+    TIMER_WINDOW.with(|w| f(w.hwnd(), &mut w.state().get_ref().borrow_mut()))
+}
+
+thread_local! {
+    static NEXT_TIMER_ID: Cell<usize> = const { Cell::new(1) };
+    static TIMER_WINDOW: Window<Timers> =
+        Window::new(WindowType::MessageOnly, Timers::default(), |timers: Pin<&Timers>, msg| {
+            if msg.msg == WM_TIMER {
+                let waker = timers.borrow_mut().get_mut(&msg.wparam).and_then(|timer| {
+                    timer.fired = true;
+                    timer.waker.take()
+                });
+                // Wake outside of the borrow, the waker is free to do anything.
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                Some(0)
+            } else {
+                None
+            }
+        })
+        .unwrap();
+}
+
+// Ids of the timers tracked by a `TimerScope`.
+type ScopeIds = Rc<RefCell<HashSet<usize>>>;
+
+crate::task_local! {
+    // Scopes of the current task, the innermost one last.
+    static TIMER_SCOPES: RefCell<Vec<ScopeIds>> = RefCell::new(Vec::new());
+}
+
+/// Converts a duration to a timer period in milliseconds, rounding up and
+/// clamping to the range supported by `SetTimer()`.
+fn timer_period(duration: Duration) -> u32 {
+    let ms = duration.as_nanos().div_ceil(1_000_000);
+    ms.clamp(USER_TIMER_MINIMUM as _, USER_TIMER_MAXIMUM as _) as u32
+}
+
+/// Periodic `SetTimer()` timer, killed when dropped.
+///
+/// Must be polled and dropped on the thread it was created on.
+struct Timer {
+    id: usize,
+    scope: Weak<RefCell<HashSet<usize>>>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Timer {
+    fn new(period: Duration) -> Self {
+        let id = NEXT_TIMER_ID.replace(NEXT_TIMER_ID.get() + 1);
+        with_timers(|_, timers| timers.insert(id, TimerState::default()));
+        let scope = TIMER_SCOPES
+            .try_with(|scopes| scopes.borrow().last().map(Rc::downgrade))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        if let Some(ids) = scope.upgrade() {
+            ids.borrow_mut().insert(id);
+        }
+        let timer = Self {
+            id,
+            scope,
+            _not_send: PhantomData,
+        };
+        timer.reset(period);
+        timer
+    }
+
+    /// Restarts the timer with a new period and discards pending ticks.
+    ///
+    /// Does nothing when the timer was killed by a `TimerScope`.
+    fn reset(&self, period: Duration) {
+        with_timers(|hwnd, timers| {
+            let Some(timer) = timers.get_mut(&self.id) else {
+                return;
+            };
+            // Calling `SetTimer()` with the id of an existing timer replaces
+            // and restarts it.
+            unsafe { SetTimer(hwnd, self.id, timer_period(period), None) };
+            timer.fired = false;
+        });
+    }
+
+    /// Returns `Ready` when the timer fired at least once since the last call.
+    ///
+    /// Multiple timer messages received between two polls are reported as a
+    /// single tick.
+    /// Never returns `Ready` once the timer was killed by a `TimerScope`.
+    fn poll_tick(&self, cx: &mut Context<'_>) -> Poll<()> {
+        with_timers(|_, timers| {
+            let Some(timer) = timers.get_mut(&self.id) else {
+                return Poll::Pending;
+            };
+            if timer.fired {
+                timer.fired = false;
+                Poll::Ready(())
+            } else {
+                match &mut timer.waker {
+                    Some(waker) if waker.will_wake(cx.waker()) => {}
+                    waker => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(ids) = self.scope.upgrade() {
+            ids.borrow_mut().remove(&self.id);
+        }
+        // The timer window is already gone when the thread exits.
+        let _ = TIMER_WINDOW.try_with(|w| {
+            unsafe { KillTimer(w.hwnd(), self.id) };
+            w.state().get_ref().borrow_mut().remove(&self.id);
+        });
+    }
+}
+
+/// Kills the timers created by the current task while the scope is alive
+/// when dropped.
+///
+/// Each timer future of this module kills its timer when dropped already. The
+/// scope is a safety net for futures that are never dropped, e.g. leaked with
+/// `mem::forget()`: No timer created within it survives once it returns or
+/// unwinds. Timers of futures still in use are killed too: Those futures are
+/// woken once so that their task notices, but they stay pending forever
+/// (a [`timeout()`] then lasts as long as its inner future). Scopes can be
+/// nested, a timer is tracked by the innermost one. Timers of other tasks,
+/// including the ones spawned within the scope, are not tracked.
+///
+/// ```
+/// use std::time::Duration;
+/// use winmsg_executor::timer::{sleep, TimerScope};
+///
+/// winmsg_executor::block_on(async {
+///     let _scope = TimerScope::new();
+///     sleep(Duration::from_millis(10)).await;
+/// });
+/// ```
+pub struct TimerScope {
+    ids: ScopeIds,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl TimerScope {
+    /// Starts tracking the timers created by the current task.
+    ///
+    /// # Panics
+    ///
+    /// Panics when not called from within a task.
+    // No `Default` impl, which is not expected to panic.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let ids = ScopeIds::default();
+        TIMER_SCOPES.with(|scopes| scopes.borrow_mut().push(ids.clone()));
+        Self {
+            ids,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for TimerScope {
+    fn drop(&mut self) {
+        // Fails when dropped with its task, the task-locals are gone then.
+        let _ = TIMER_SCOPES.try_with(|scopes| {
+            scopes
+                .borrow_mut()
+                .retain(|ids| !Rc::ptr_eq(ids, &self.ids));
+        });
+        let ids = mem::take(&mut *self.ids.borrow_mut());
+        // The timer window is already gone when the thread exits.
+        let wakers = TIMER_WINDOW
+            .try_with(|w| {
+                let mut timers = w.state().get_ref().borrow_mut();
+                ids.into_iter()
+                    .filter_map(|id| {
+                        unsafe { KillTimer(w.hwnd(), id) };
+                        timers.remove(&id)?.waker
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        // Wake outside of the borrow, the killed timers stay pending.
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Waits until `duration` has elapsed.
+///
+/// The timer starts when the returned future is polled for the first time.
+/// Durations are rounded up to full milliseconds, with a minimum of 10ms
+/// (`USER_TIMER_MINIMUM`). See the [module level documentation](self) for notes
+/// on accuracy.
+///
+/// Dropping the future before it completes cancels the timer. Never completes
+/// when the timer is killed by a [`TimerScope`].
+pub async fn sleep(duration: Duration) {
+    let timer = Timer::new(duration);
+    poll_fn(|cx| timer.poll_tick(cx)).await;
+}
+
+/// Spawns a task on the current thread that runs `future` once `delay` has
+/// elapsed.
+///
+/// Same as spawning `sleep(delay).await` followed by `future.await` with
+/// [`spawn_local()`]. Aborting the task with [`JoinHandle::abort()`] during
+/// the delay cancels the timer, `future` is dropped without being polled.
+/// Like for any other task, dropping the handle lets the task run.
+pub fn spawn_after<T>(delay: Duration, future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    spawn_local(async move {
+        sleep(delay).await;
+        future.await
+    })
+}
+
+/// Quits the message loop of the current thread once `delay` has elapsed.
+///
+/// Spawns a task that posts a quit message with exit code 0, so that
+/// [`MessageLoop::run`](crate::MessageLoop::run) returns `Some(0)`. Meant for
+/// tests and demos. Abort the returned task to cancel the quit. Note that
+/// [`block_on()`](crate::block_on) panics when it receives the quit message.
+pub fn quit_after(delay: Duration) -> JoinHandle<()> {
+    spawn_after(delay, async { unsafe { PostQuitMessage(0) } })
+}
+
+// High resolution waitable timer, closed when dropped.
+struct WaitableTimer(HANDLE);
+
+impl WaitableTimer {
+    // Returns `None` when high resolution timers are not supported.
+    fn start(duration: Duration) -> Option<Self> {
+        let handle = unsafe {
+            CreateWaitableTimerExW(
+                ptr::null(),
+                ptr::null(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS,
+            )
+        };
+        if handle.is_null() {
+            return None;
+        }
+        let timer = Self(handle);
+        // Negative due times are relative, in units of 100ns.
+        let due_time = -(duration.as_nanos().div_ceil(100).min(i64::MAX as u128) as i64);
+        let started =
+            unsafe { SetWaitableTimer(handle, &due_time, 0, None, ptr::null(), FALSE) } != 0;
+        started.then_some(timer)
+    }
+}
+
+impl Drop for WaitableTimer {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Waits until `duration` has elapsed, using a high resolution timer.
+///
+/// Unlike [`sleep()`], the delay is not rounded to the system timer tick.
+/// The accuracy comes at the cost of an additional kernel object and a wait
+/// on the system thread pool, see [`wait_for_handle()`]. Dropping the future
+/// cancels the timer.
+///
+/// High resolution waitable timers require Windows 10, version 1803 or
+/// later. Falls back to [`sleep()`] on older versions.
+pub async fn sleep_precise(duration: Duration) {
+    if let Some(timer) = WaitableTimer::start(duration) {
+        if wait_for_handle(timer.0).await.is_ok() {
+            return;
+        }
+    }
+    sleep(duration).await;
+}
+
+/// Error returned by [`timeout()`] when the deadline has elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// Runs `future` to completion or until `duration` has elapsed, whichever
+/// happens first.
+///
+/// Returns [`Elapsed`] when the timer fires before the future completes. The
+/// future is dropped without being polled again in that case. When the future
+/// completes first, the timer is cancelled right away.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let mut future = pin!(future);
+    let mut sleep = pin!(sleep(duration));
+    poll_fn(|cx| {
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            Poll::Ready(Ok(result))
+        } else {
+            sleep.as_mut().poll(cx).map(|()| Err(Elapsed))
+        }
+    })
+    .await
+}
+
+/// Stream of periodic ticks, created by [`interval()`].
+///
+/// Ticks missed because the task was busy are coalesced: At most one tick is
+/// yielded per poll, no matter how many periods have elapsed since the last
+/// one. The schedule is not adjusted to catch up: After a late tick, the next
+/// one is yielded a full period after it was observed (like tokio's
+/// `MissedTickBehavior::Delay`).
+///
+/// The underlying timer is stopped when the `Interval` is dropped.
+pub struct Interval {
+    timer: Timer,
+    period: Duration,
+    // Latest expected time of the next tick, a tick observed later was
+    // delayed.
+    deadline: Instant,
+}
+
+// Lateness of `WM_TIMER` messages caused by the system timer tick, which
+// does not count as a delayed tick.
+const TICK_SLACK: Duration = Duration::from_millis(16);
+
+/// Creates a new [`Interval`] that yields with `period` between ticks.
+///
+/// In contrast to tokio's implementation, the first tick is yielded after one
+/// `period` instead of immediately. Periods are rounded like in [`sleep()`].
+pub fn interval(period: Duration) -> Interval {
+    let now = Instant::now();
+    let mut interval = Interval {
+        timer: Timer::new(period),
+        period,
+        deadline: now,
+    };
+    interval.set_deadline(now);
+    interval
+}
+
+impl Interval {
+    /// Waits for the next tick and returns its time of observation.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next tick.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        ready!(self.timer.poll_tick(cx));
+        let now = Instant::now();
+        if now > self.deadline {
+            // The periodic timer keeps its phase, restart it to delay the
+            // following ticks.
+            self.timer.reset(self.period);
+        }
+        self.set_deadline(now);
+        Poll::Ready(now)
+    }
+
+    /// Restarts the schedule, the next tick is yielded one period from now.
+    pub fn reset(&mut self) {
+        self.timer.reset(self.period);
+        self.set_deadline(Instant::now());
+    }
+
+    // Expects the next tick one (rounded) period after `last_tick`.
+    fn set_deadline(&mut self, last_tick: Instant) {
+        let period = Duration::from_millis(timer_period(self.period).into());
+        self.deadline = last_tick + period + TICK_SLACK;
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+/// Stream adapter created by [`debounce()`].
+pub struct Debounce<S: Stream> {
+    // `None` once the upstream stream has ended.
+    stream: Option<Pin<Box<S>>>,
+    latest: Option<S::Item>,
+    timer: Option<Timer>,
+    quiet_period: Duration,
+}
+
+/// Yields the latest item of `stream` once no new item was received for
+/// `quiet_period`.
+///
+/// Each item restarts the timer and replaces the item waiting to be yielded,
+/// so bursts of items (e.g. `EN_CHANGE` notifications while typing) result in
+/// a single item. When the upstream stream ends, the item still waiting is
+/// yielded after `quiet_period` before the stream ends too. Periods are
+/// rounded like in [`sleep()`].
+pub fn debounce<S: Stream>(stream: S, quiet_period: Duration) -> Debounce<S> {
+    Debounce {
+        stream: Some(Box::pin(stream)),
+        latest: None,
+        timer: None,
+        quiet_period,
+    }
+}
+
+// The upstream stream is pinned in a box, items are never pinned.
+impl<S: Stream> Unpin for Debounce<S> {}
+
+impl<S: Stream> Stream for Debounce<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = self.get_mut();
+        while let Some(stream) = &mut this.stream {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    this.latest = Some(item);
+                    match &this.timer {
+                        Some(timer) => timer.reset(this.quiet_period),
+                        None => this.timer = Some(Timer::new(this.quiet_period)),
+                    }
+                }
+                Poll::Ready(None) => this.stream = None,
+                Poll::Pending => break,
+            }
+        }
+
+        let Some(timer) = &this.timer else {
+            return if this.stream.is_none() {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            };
+        };
+        if timer.poll_tick(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.timer = None;
+        Poll::Ready(this.latest.take())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{block_on, FilterResult, MessageLoop};
+
+    fn active_timers() -> usize {
+        with_timers(|_, timers| timers.len())
+    }
+
+    #[test]
+    fn period_rounding() {
+        assert_eq!(timer_period(Duration::ZERO), USER_TIMER_MINIMUM);
+        assert_eq!(timer_period(Duration::from_nanos(1)), USER_TIMER_MINIMUM);
+        assert_eq!(timer_period(Duration::from_micros(10_001)), 11);
+        assert_eq!(timer_period(Duration::MAX), USER_TIMER_MAXIMUM);
+    }
+
+    #[test]
+    fn timer_scope_kills_leaked_timers() {
+        let leak_sleep = || {
+            let mut sleep = Box::pin(sleep(Duration::from_secs(60)));
+            assert!(crate::poll_ready(sleep.as_mut()).is_err());
+            mem::forget(sleep);
+        };
+        block_on(async {
+            let outer = TimerScope::new();
+            {
+                let _inner = TimerScope::new();
+                leak_sleep();
+                // Completes and kills its own timer.
+                sleep(Duration::from_millis(10)).await;
+                assert_eq!(active_timers(), 1);
+            }
+            assert_eq!(active_timers(), 0);
+
+            // Tracked by the outer scope again.
+            leak_sleep();
+            drop(outer);
+            assert_eq!(active_timers(), 0);
+        });
+    }
+
+    #[test]
+    fn timer_scope_wakes_live_timers() {
+        block_on(async {
+            let mut scope = Some(TimerScope::new());
+            let mut long_sleep = pin!(sleep(Duration::from_secs(60)));
+            let mut polls = 0;
+            poll_fn(|cx| {
+                polls += 1;
+                assert!(long_sleep.as_mut().poll(cx).is_pending());
+                if polls == 2 {
+                    return Poll::Ready(());
+                }
+                // Killing the timer installed by the first poll wakes the
+                // task, without completing the sleep.
+                scope = None;
+                Poll::Pending
+            })
+            .await;
+            assert_eq!(active_timers(), 0);
+        });
+    }
+
+    #[test]
+    fn sleep_elapsed() {
+        let start = Instant::now();
+        block_on(sleep(Duration::from_millis(50)));
+        // Generous tolerance: `WM_TIMER` is aligned to the system timer tick.
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(active_timers(), 0);
+    }
+
+    #[test]
+    fn sleep_precise_elapsed() {
+        let start = Instant::now();
+        block_on(sleep_precise(Duration::from_millis(2)));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1));
+        assert!(elapsed < Duration::from_millis(500));
+        assert_eq!(active_timers(), 0);
+    }
+
+    #[test]
+    fn sleep_cancel() {
+        block_on(async {
+            {
+                let mut long_sleep = pin!(sleep(Duration::from_secs(60)));
+                // Poll once to install the timer.
+                let _ = crate::poll_ready(long_sleep.as_mut());
+                assert_eq!(active_timers(), 1);
+            }
+            assert_eq!(active_timers(), 0);
+
+            // The message loop keeps running without a stray timer.
+            sleep(Duration::from_millis(20)).await;
+        });
+    }
+
+    #[test]
+    fn spawn_after_delay() {
+        let start = Instant::now();
+        let task = spawn_after(Duration::from_millis(50), async { 42 });
+        assert_eq!(block_on(task).unwrap(), 42);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn spawn_after_abort() {
+        let polled = Rc::new(Cell::new(false));
+        let task = spawn_after(Duration::from_secs(60), {
+            let polled = polled.clone();
+            async move { polled.set(true) }
+        });
+        block_on(crate::yield_now());
+        assert_eq!(active_timers(), 1);
+
+        task.abort();
+        block_on(crate::yield_now());
+        assert_eq!(active_timers(), 0);
+        assert!(!polled.get());
+    }
+
+    #[test]
+    fn debounce_bursts() {
+        let (tx, rx) = crate::util::channel(4);
+        spawn_local(async move {
+            tx.send(1).unwrap();
+            tx.send(2).unwrap();
+            sleep(Duration::from_millis(100)).await;
+            // Yielded after the stream ended.
+            tx.send(3).unwrap();
+        });
+
+        let mut debounced = debounce(rx, Duration::from_millis(30));
+        let items = block_on(async {
+            let mut items = Vec::new();
+            while let Some(item) = poll_fn(|cx| Pin::new(&mut debounced).poll_next(cx)).await {
+                items.push(item);
+            }
+            items
+        });
+        assert_eq!(items, [2, 3]);
+        assert_eq!(active_timers(), 0);
+    }
+
+    #[test]
+    fn quit_after_delay() {
+        let start = Instant::now();
+        quit_after(Duration::from_millis(20));
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(0));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        // An aborted quit never happens.
+        quit_after(Duration::from_millis(10)).abort();
+        spawn_after(Duration::from_millis(50), async {
+            unsafe { PostQuitMessage(1) }
+        });
+        assert_eq!(MessageLoop::run(|_, _| FilterResult::Forward), Some(1));
+    }
+
+    #[test]
+    fn timeout_elapsed() {
+        block_on(async {
+            let result = timeout(Duration::from_millis(10), sleep(Duration::from_secs(60))).await;
+            assert_eq!(result, Err(Elapsed));
+            assert_eq!(active_timers(), 0);
+        });
+    }
+
+    #[test]
+    fn timeout_completed() {
+        block_on(async {
+            let result = timeout(Duration::from_secs(60), async { 42 }).await;
+            assert_eq!(result, Ok(42));
+            assert_eq!(active_timers(), 0);
+        });
+    }
+
+    #[test]
+    fn interval_ticks() {
+        block_on(async {
+            let mut interval = interval(Duration::from_millis(20));
+            let mut last_tick = Instant::now();
+            for _ in 0..3 {
+                let tick = interval.tick().await;
+                assert!(tick > last_tick);
+                last_tick = tick;
+            }
+            drop(interval);
+            assert_eq!(active_timers(), 0);
+        });
+    }
+
+    #[test]
+    fn interval_coalesce_missed_ticks() {
+        block_on(async {
+            let mut interval = interval(Duration::from_millis(10));
+
+            // Block the thread for many periods.
+            std::thread::sleep(Duration::from_millis(100));
+
+            interval.tick().await;
+            // Only a single tick was pending, the next one is not ready yet.
+            assert!(crate::poll_ready(interval.tick()).is_err());
+        });
+    }
+
+    #[test]
+    fn interval_delay_after_missed_ticks() {
+        block_on(async {
+            let mut interval = interval(Duration::from_millis(100));
+            interval.tick().await;
+
+            // Block the thread past the next tick.
+            std::thread::sleep(Duration::from_millis(150));
+
+            let late_tick = interval.tick().await;
+            // A full period later instead of at the next multiple of the
+            // period, which is 50ms away.
+            let next_tick = interval.tick().await;
+            assert!(next_tick - late_tick >= Duration::from_millis(80));
+        });
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    cell::{Cell, RefCell, UnsafeCell},
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Mutual exclusion across `.await` points for tasks of the same thread.
+///
+/// Unlike a `RefCell`, which panics when a borrow is held across an `.await`
+/// point while another task tries to borrow, [`AsyncMutex::lock`] waits until
+/// the value is available. Waiting tasks are woken in the order they started
+/// waiting, with a wake message posted to the message loop when the guard is
+/// dropped.
+///
+/// The mutex is confined to one thread, it uses no atomics.
+pub struct AsyncMutex<T> {
+    locked: Cell<bool>,
+    waiters: RefCell<VecDeque<(usize, Waker)>>,
+    next_waiter_id: Cell<usize>,
+    value: UnsafeCell<T>,
+}
+
+/// Exclusive access to the value of an [`AsyncMutex`], unlocked when dropped.
+pub struct AsyncMutexGuard<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<T> AsyncMutex<T> {
+    /// Creates a new unlocked mutex.
+    pub fn new(value: T) -> Self {
+        Self {
+            locked: Cell::new(false),
+            waiters: RefCell::new(VecDeque::new()),
+            next_waiter_id: Cell::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Waits until the mutex is unlocked and locks it.
+    pub fn lock(&self) -> impl Future<Output = AsyncMutexGuard<'_, T>> + '_ {
+        Lock {
+            mutex: self,
+            waiter_id: None,
+        }
+    }
+
+    /// Locks the mutex if it is unlocked.
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        if self.locked.replace(true) {
+            None
+        } else {
+            Some(AsyncMutexGuard { mutex: self })
+        }
+    }
+
+    /// Returns a mutable reference to the value, no locking required.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the mutex and returns the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn wake_first_waiter(&self) {
+        let waker = self
+            .waiters
+            .borrow()
+            .front()
+            .map(|(_, waker)| waker.clone());
+        // Wake outside of the borrow, the waker is free to do anything.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for AsyncMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("AsyncMutex");
+        match self.try_lock() {
+            Some(guard) => d.field("value", &&*guard),
+            None => d.field("value", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+impl<T> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The guard has exclusive access while the mutex is locked.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The guard has exclusive access while the mutex is locked.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.set(false);
+        self.mutex.wake_first_waiter();
+    }
+}
+
+// A waiter stays queued after being woken until it acquires the lock or is
+// dropped. This way no wake up gets lost when the woken task is dropped or
+// another task locks the mutex first.
+struct Lock<'a, T> {
+    mutex: &'a AsyncMutex<T>,
+    waiter_id: Option<usize>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        if let Some(guard) = mutex.try_lock() {
+            if let Some(id) = self.waiter_id.take() {
+                mutex.waiters.borrow_mut().retain(|(i, _)| *i != id);
+            }
+            return Poll::Ready(guard);
+        }
+
+        let mut waiters = mutex.waiters.borrow_mut();
+        match self.waiter_id {
+            Some(id) => {
+                let (_, waker) = waiters.iter_mut().find(|(i, _)| *i == id).unwrap();
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let id = mutex.next_waiter_id.replace(mutex.next_waiter_id.get() + 1);
+                waiters.push_back((id, cx.waker().clone()));
+                self.waiter_id = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        let Some(id) = self.waiter_id else {
+            return;
+        };
+        let was_first = {
+            let mut waiters = self.mutex.waiters.borrow_mut();
+            let was_first = waiters.front().is_some_and(|(i, _)| *i == id);
+            waiters.retain(|(i, _)| *i != id);
+            was_first
+        };
+        // Pass on a wake up this waiter might have received.
+        if was_first && !self.mutex.locked.get() {
+            self.mutex.wake_first_waiter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{block_on, spawn_local, yield_now};
+
+    #[test]
+    fn serialize_across_await() {
+        let mutex = Rc::new(AsyncMutex::new(Vec::new()));
+        let tasks: Vec<_> = (0..3)
+            .map(|i| {
+                let mutex = mutex.clone();
+                spawn_local(async move {
+                    let mut log = mutex.lock().await;
+                    log.push(i);
+                    yield_now().await;
+                    log.push(i);
+                })
+            })
+            .collect();
+        block_on(async {
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        assert_eq!(*mutex.try_lock().unwrap(), [0, 0, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn dropped_waiter_passes_on_wake_up() {
+        let mutex = Rc::new(AsyncMutex::new(()));
+        let spawn_waiter = || {
+            let mutex = mutex.clone();
+            spawn_local(async move { drop(mutex.lock().await) })
+        };
+
+        let guard = mutex.try_lock().unwrap();
+        let first = spawn_waiter();
+        let second = spawn_waiter();
+        block_on(yield_now());
+
+        // Wakes the first waiter, which is dropped before it can lock.
+        drop(guard);
+        first.abort();
+        block_on(second).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io,
+    pin::Pin,
+    slice,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    Foundation::*,
+    UI::WindowsAndMessaging::{GetAncestor, GetDesktopWindow, GA_PARENT, WM_SETTINGCHANGE},
+};
+
+use super::{
+    channel, message_stream::Subscription, ChannelReceiver, CreateStage, Window,
+    WindowCreationError, WindowType, MESSAGE_STREAM_CAPACITY,
+};
+
+/// Broadcast message received by a [`BroadcastStream`].
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub msg: u32,
+    pub wparam: WPARAM,
+    /// Must not be dereferenced: Pointers are only valid while the message
+    /// is dispatched, see [`Broadcast::setting`].
+    pub lparam: LPARAM,
+    /// Name of the changed system parameter for `WM_SETTINGCHANGE` messages,
+    /// e.g. `ImmersiveColorSet` when switching between dark and light mode.
+    /// Copied from the string `lparam` points to.
+    pub setting: Option<String>,
+}
+
+/// Stream of broadcast messages, like `WM_SETTINGCHANGE` or
+/// `WM_DISPLAYCHANGE`, sent to all top-level windows.
+///
+/// Up to [`MESSAGE_STREAM_CAPACITY`] messages are buffered while the stream
+/// is not polled. Ends when the window is destroyed.
+///
+/// ```no_run
+/// use windows_sys::Win32::UI::WindowsAndMessaging::WM_SETTINGCHANGE;
+/// use winmsg_executor::util::BroadcastStream;
+///
+/// let mut broadcasts = BroadcastStream::new(|msg| msg == WM_SETTINGCHANGE).unwrap();
+/// winmsg_executor::block_on(async {
+///     while let Some(broadcast) = broadcasts.recv().await {
+///         if broadcast.setting.as_deref() == Some("ImmersiveColorSet") {
+///             println!("dark mode toggled");
+///         }
+///     }
+/// });
+/// ```
+pub struct BroadcastStream {
+    receiver: ChannelReceiver<Broadcast>,
+    _subscription: Option<Subscription>,
+    // Hidden window created by `BroadcastStream::new()`.
+    _window: Option<Window<()>>,
+}
+
+impl BroadcastStream {
+    /// Creates a hidden [`WindowType::TopLevel`] window to receive the
+    /// broadcast messages for which `filter` returns `true`.
+    pub fn new(filter: impl Fn(u32) -> bool + 'static) -> Result<Self, WindowCreationError> {
+        let window = Window::new(WindowType::TopLevel, (), |_, _| None)?;
+        let mut stream = window.broadcasts(filter).map_err(|_| WindowCreationError {
+            code: 0,
+            stage: CreateStage::Subclass,
+        })?;
+        stream._window = Some(window);
+        Ok(stream)
+    }
+
+    /// Receives the next broadcast message, `None` when the window was
+    /// destroyed.
+    pub async fn recv(&mut self) -> Option<Broadcast> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for BroadcastStream {
+    type Item = Broadcast;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Broadcast>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl<S> Window<S> {
+    /// Returns a stream of the broadcast messages for which `filter` returns
+    /// `true`.
+    ///
+    /// Fails for message-only windows and child windows, which do not
+    /// receive broadcast messages. Like [`Window::messages()`], but decodes
+    /// the message parameters while the message is dispatched.
+    pub fn broadcasts(
+        &self,
+        filter: impl Fn(u32) -> bool + 'static,
+    ) -> io::Result<BroadcastStream> {
+        // The parent of message-only windows is a hidden message window.
+        if unsafe { GetAncestor(self.hwnd(), GA_PARENT) != GetDesktopWindow() } {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only top-level windows receive broadcast messages",
+            ));
+        }
+
+        let (sender, receiver) = channel(MESSAGE_STREAM_CAPACITY);
+        let subscription = Subscription::new(self.hwnd(), move |msg| {
+            if !filter(msg.msg) {
+                return;
+            }
+            // A nul terminated UTF-16 string, the window is a Unicode window.
+            let setting = (msg.msg == WM_SETTINGCHANGE && msg.lparam != 0).then(|| unsafe {
+                let setting = msg.lparam as *const u16;
+                let len = (0..).take_while(|&i| *setting.add(i) != 0).count();
+                String::from_utf16_lossy(slice::from_raw_parts(setting, len))
+            });
+            // Drop messages while the stream is full.
+            let _ = sender.send(Broadcast {
+                msg: msg.msg,
+                wparam: msg.wparam,
+                lparam: msg.lparam,
+                setting,
+            });
+        });
+        Ok(BroadcastStream {
+            receiver,
+            _subscription: subscription,
+            _window: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use windows_sys::Win32::UI::WindowsAndMessaging::WM_DISPLAYCHANGE;
+
+    use super::*;
+    use crate::poll_ready;
+
+    #[test]
+    fn setting_change() {
+        let w = Window::new(WindowType::TopLevel, (), |_, _| None).unwrap();
+        let mut broadcasts = w
+            .broadcasts(|msg| msg == WM_SETTINGCHANGE || msg == WM_DISPLAYCHANGE)
+            .unwrap();
+        unsafe {
+            let setting = windows_sys::w!("ImmersiveColorSet");
+            w.send(WM_SETTINGCHANGE, 0, setting as LPARAM);
+            w.send(WM_DISPLAYCHANGE, 32, 0);
+        }
+
+        let broadcast = poll_ready(broadcasts.recv()).unwrap().unwrap();
+        assert_eq!(broadcast.setting.as_deref(), Some("ImmersiveColorSet"));
+        let broadcast = poll_ready(broadcasts.recv()).unwrap().unwrap();
+        assert_eq!((broadcast.msg, broadcast.wparam), (WM_DISPLAYCHANGE, 32));
+        assert!(broadcast.setting.is_none());
+
+        drop(w);
+        assert!(poll_ready(broadcasts.recv()).unwrap().is_none());
+    }
+
+    #[test]
+    fn message_only_rejected() {
+        let w = Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap();
+        assert!(w.broadcasts(|_| true).is_err());
+        assert!(BroadcastStream::new(|_| true).is_ok());
+    }
+}
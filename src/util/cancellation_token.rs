@@ -0,0 +1,152 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    future::poll_fn,
+    rc::{Rc, Weak},
+    task::{Poll, Waker},
+};
+
+#[derive(Default)]
+struct Node {
+    cancelled: bool,
+    wakers: Vec<Waker>,
+    children: Vec<Weak<RefCell<Node>>>,
+}
+
+/// Signals cancellation to tasks of the same thread.
+///
+/// Tasks race their work against [`CancellationToken::cancelled`] (e.g. with
+/// [`race()`](crate::combinator::race)) to stop cooperatively once
+/// [`CancellationToken::cancel`] was called. Clones share the same state.
+///
+/// Tokens created with [`CancellationToken::child_token`] form a tree: Cancelling
+/// a token also cancels all of its children, but not its parent.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Rc<RefCell<Node>>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token that is cancelled together with this one.
+    ///
+    /// Cancelling the child does not affect this token.
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+        let mut node = self.node.borrow_mut();
+        if node.cancelled {
+            child.node.borrow_mut().cancelled = true;
+        } else {
+            node.children.retain(|child| child.strong_count() > 0);
+            node.children.push(Rc::downgrade(&child.node));
+        }
+        child
+    }
+
+    /// Cancels the token and its children, wakes all tasks waiting in
+    /// [`CancellationToken::cancelled`].
+    pub fn cancel(&self) {
+        let (wakers, children) = {
+            let mut node = self.node.borrow_mut();
+            if node.cancelled {
+                return;
+            }
+            node.cancelled = true;
+            (
+                std::mem::take(&mut node.wakers),
+                std::mem::take(&mut node.children),
+            )
+        };
+        // Wake outside of the borrow, the waker is free to do anything.
+        for waker in wakers {
+            waker.wake();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            CancellationToken { node: child }.cancel();
+        }
+    }
+
+    /// Returns `true` when the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.node.borrow().cancelled
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        poll_fn(|cx| {
+            let mut node = self.node.borrow_mut();
+            if node.cancelled {
+                return Poll::Ready(());
+            }
+            if !node.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                node.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        block_on,
+        combinator::{race, Either},
+        spawn_local,
+        timer::sleep,
+        yield_now,
+    };
+
+    #[test]
+    fn cancel_wakes_waiters() {
+        let token = CancellationToken::new();
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let token = token.clone();
+                spawn_local(
+                    async move { race(token.cancelled(), sleep(Duration::from_secs(60))).await },
+                )
+            })
+            .collect();
+        block_on(async {
+            yield_now().await;
+            token.cancel();
+            for task in tasks {
+                assert_eq!(task.await.unwrap(), Either::Left(()));
+            }
+        });
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn child_tokens() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grandchild = child.child_token();
+
+        child.cancel();
+        assert!(!parent.is_cancelled());
+        assert!(grandchild.is_cancelled());
+
+        let other_child = parent.child_token();
+        parent.cancel();
+        assert!(other_child.is_cancelled());
+        assert!(parent.child_token().is_cancelled());
+        block_on(other_child.cancelled());
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    future::poll_fn,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+
+struct Shared<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver_alive: bool,
+    waker: Option<Waker>,
+}
+
+/// Error returned by [`ChannelSender::send`], contains the value that could
+/// not be sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// The channel is at capacity.
+    Full(T),
+
+    /// The receiver was dropped.
+    Disconnected(T),
+}
+
+impl<T> SendError<T> {
+    /// Returns the value that could not be sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Full(value) | SendError::Disconnected(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full(_) => f.write_str("Full(..)"),
+            SendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full(_) => f.write_str("channel is full"),
+            SendError::Disconnected(_) => f.write_str("channel receiver dropped"),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Sending half of a channel created by [`channel()`], can be cloned to send
+/// from multiple places.
+pub struct ChannelSender<T> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+/// Receiving half of a channel created by [`channel()`].
+///
+/// Streams the values in the order they were sent. The stream ends when all
+/// senders were dropped and all buffered values were received.
+pub struct ChannelReceiver<T> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+/// Creates a bounded channel to send values to a task on the same thread.
+///
+/// Sending never blocks, it fails when `capacity` values are buffered. The
+/// receiving task is woken with a wake message posted to the message loop.
+/// Use [`oneshot()`](super::oneshot()) to send a value across threads.
+///
+/// # Panics
+///
+/// Panics when `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (ChannelSender<T>, ChannelReceiver<T>) {
+    assert!(capacity > 0, "channel capacity must be greater than 0");
+    let shared = Rc::new(RefCell::new(Shared {
+        queue: VecDeque::new(),
+        capacity,
+        senders: 1,
+        receiver_alive: true,
+        waker: None,
+    }));
+    (
+        ChannelSender {
+            shared: shared.clone(),
+        },
+        ChannelReceiver { shared },
+    )
+}
+
+impl<T> ChannelSender<T> {
+    /// Sends a value without waiting.
+    ///
+    /// Returns the value back when the channel is full or the receiver was
+    /// dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let waker = {
+            let mut shared = self.shared.borrow_mut();
+            if !shared.receiver_alive {
+                return Err(SendError::Disconnected(value));
+            }
+            if shared.queue.len() >= shared.capacity {
+                return Err(SendError::Full(value));
+            }
+            shared.queue.push_back(value);
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for ChannelSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for ChannelSender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut shared = self.shared.borrow_mut();
+            shared.senders -= 1;
+            if shared.senders > 0 {
+                return;
+            }
+            shared.waker.take()
+        };
+        // Wake the receiver to end the stream.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> ChannelReceiver<T> {
+    /// Receives the next value, `None` when all senders were dropped.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<T> Drop for ChannelReceiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.receiver_alive = false;
+        // Drop buffered values right away instead of when the last sender is
+        // dropped.
+        let queue = std::mem::take(&mut shared.queue);
+        drop(shared);
+        drop(queue);
+    }
+}
+
+impl<T> Stream for ChannelReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut shared = self.shared.borrow_mut();
+        if let Some(value) = shared.queue.pop_front() {
+            Poll::Ready(Some(value))
+        } else if shared.senders == 0 {
+            Poll::Ready(None)
+        } else {
+            match &mut shared.waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                waker => *waker = Some(cx.waker().clone()),
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{block_on, spawn_local, yield_now};
+
+    #[test]
+    fn fan_in() {
+        let (tx, mut rx) = channel(4);
+        for i in 0..2 {
+            let tx = tx.clone();
+            spawn_local(async move {
+                for j in 0..2 {
+                    tx.send(i * 10 + j).unwrap();
+                    yield_now().await;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut received = block_on(async {
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await {
+                received.push(value);
+            }
+            received
+        });
+        received.sort();
+        assert_eq!(received, [0, 1, 10, 11]);
+    }
+
+    #[test]
+    fn send_errors() {
+        let (tx, rx) = channel(1);
+        tx.send(1).unwrap();
+        assert_eq!(tx.send(2), Err(SendError::Full(2)));
+        drop(rx);
+        assert_eq!(tx.send(3), Err(SendError::Disconnected(3)));
+    }
+}
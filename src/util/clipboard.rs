@@ -0,0 +1,129 @@
+//! Clipboard text access without blocking the message loop.
+//!
+//! Only one window can open the clipboard at a time. When another application
+//! holds it open, the functions of this module retry with an increasing
+//! delay, awaiting a [`sleep()`](crate::timer::sleep) in between, so that the
+//! message loop keeps running.
+
+use std::{io, ptr, slice, time::Duration};
+
+use windows_sys::Win32::{
+    Foundation::*,
+    System::{
+        DataExchange::*,
+        Memory::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE},
+        Ole::CF_UNICODETEXT,
+    },
+};
+
+use super::{Window, WindowType};
+use crate::timer::sleep;
+
+// Retries for about one second in total before giving up.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_millis(512);
+
+// Closes the clipboard when dropped.
+struct OpenClipboardGuard;
+
+impl Drop for OpenClipboardGuard {
+    fn drop(&mut self) {
+        unsafe { CloseClipboard() };
+    }
+}
+
+async fn open_clipboard(owner: HWND) -> io::Result<OpenClipboardGuard> {
+    let mut delay = FIRST_RETRY_DELAY;
+    loop {
+        if unsafe { OpenClipboard(owner) } != 0 {
+            return Ok(OpenClipboardGuard);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(ERROR_ACCESS_DENIED as i32) || delay > MAX_RETRY_DELAY {
+            return Err(err);
+        }
+        sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Returns the text on the clipboard.
+///
+/// Fails with [`io::ErrorKind::NotFound`] when the clipboard contains no text
+/// and with `ERROR_ACCESS_DENIED` when another application keeps the
+/// clipboard open for too long. Invalid UTF-16 is replaced with
+/// [`U+FFFD`](char::REPLACEMENT_CHARACTER).
+pub async fn get_text() -> io::Result<String> {
+    let _clipboard = open_clipboard(ptr::null_mut()).await?;
+    unsafe {
+        let data = GetClipboardData(CF_UNICODETEXT.into());
+        if data.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "clipboard contains no text",
+            ));
+        }
+        let text = GlobalLock(data) as *const u16;
+        if text.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let text = slice::from_raw_parts(text, GlobalSize(data) / 2);
+        // The size of the allocation may be larger than the nul terminated text.
+        let len = text.iter().position(|&c| c == 0).unwrap_or(text.len());
+        let text = String::from_utf16_lossy(&text[..len]);
+        GlobalUnlock(data);
+        Ok(text)
+    }
+}
+
+/// Replaces the content of the clipboard with `text`.
+///
+/// Fails with `ERROR_ACCESS_DENIED` when another application keeps the
+/// clipboard open for too long.
+pub async fn set_text(text: &str) -> io::Result<()> {
+    // `EmptyClipboard()` requires an owner window, otherwise setting the data
+    // fails. The data outlives the window.
+    let owner = Window::new(WindowType::MessageOnly, (), |_, _| None)
+        .map_err(|_| io::Error::other("cannot create clipboard owner window"))?;
+    let _clipboard = open_clipboard(owner.hwnd()).await?;
+
+    let text: Vec<u16> = text.encode_utf16().chain([0]).collect();
+    unsafe {
+        if EmptyClipboard() == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let data = GlobalAlloc(GMEM_MOVEABLE, text.len() * 2);
+        if data.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let dst = GlobalLock(data) as *mut u16;
+        if dst.is_null() {
+            let err = io::Error::last_os_error();
+            GlobalFree(data);
+            return Err(err);
+        }
+        ptr::copy_nonoverlapping(text.as_ptr(), dst, text.len());
+        GlobalUnlock(data);
+        // The system owns the memory after a successful call.
+        if SetClipboardData(CF_UNICODETEXT.into(), data).is_null() {
+            let err = io::Error::last_os_error();
+            GlobalFree(data);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn text_round_trip() {
+        block_on(async {
+            set_text("grüße 🦀").await.unwrap();
+            assert_eq!(get_text().await.unwrap(), "grüße 🦀");
+        });
+    }
+}
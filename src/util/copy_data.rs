@@ -0,0 +1,111 @@
+use std::{io, ptr, slice};
+
+use windows_sys::Win32::{
+    Foundation::*,
+    System::DataExchange::COPYDATASTRUCT,
+    UI::WindowsAndMessaging::{SendMessageW, WM_COPYDATA},
+};
+
+use super::{Window, WindowMessage};
+
+/// Decoder for [`WM_COPYDATA`] messages sent with [`Window::send_copy_data()`].
+///
+/// [`WM_COPYDATA`]: https://learn.microsoft.com/en-us/windows/win32/dataxchg/wm-copydata
+pub struct CopyData;
+
+impl CopyData {
+    /// Returns the bytes of a `WM_COPYDATA` message, `None` for other messages.
+    ///
+    /// The bytes are owned by the sender and only valid while the message is
+    /// dispatched: The returned slice borrows `msg`, copy the bytes to keep
+    /// them around after the `wndproc` closure returns.
+    ///
+    /// # Safety
+    ///
+    /// `msg` must be a message received by the `wndproc` closure, which is
+    /// still running.
+    pub unsafe fn from_message(msg: &WindowMessage) -> Option<&[u8]> {
+        if msg.msg != WM_COPYDATA {
+            return None;
+        }
+        let copy_data = &*(msg.lparam as *const COPYDATASTRUCT);
+        if copy_data.cbData == 0 {
+            return Some(&[]);
+        }
+        Some(slice::from_raw_parts(
+            copy_data.lpData.cast(),
+            copy_data.cbData as usize,
+        ))
+    }
+}
+
+impl<S> Window<S> {
+    /// Sends `data` to the window `target`, which may belong to another
+    /// process, with a `WM_COPYDATA` message.
+    ///
+    /// Blocks until the target window processed the message. The system
+    /// copies the data to the target process, where it is decoded with
+    /// [`CopyData::from_message()`]. Fails when the target window did not
+    /// return `TRUE` or when `data` is larger than 4 GiB.
+    // Window handles are not dereferenced, `SendMessageW()` fails for invalid ones.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn send_copy_data(&self, target: HWND, data: &[u8]) -> io::Result<()> {
+        let copy_data = COPYDATASTRUCT {
+            dwData: 0,
+            cbData: data
+                .len()
+                .try_into()
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+            lpData: data.as_ptr().cast_mut().cast(),
+        };
+        let handled = unsafe {
+            // Tells apart an invalid target handle from an unhandled message.
+            SetLastError(0);
+            SendMessageW(
+                target,
+                WM_COPYDATA,
+                self.hwnd() as WPARAM,
+                ptr::from_ref(&copy_data) as LPARAM,
+            )
+        };
+        if handled == 0 {
+            let err = io::Error::last_os_error();
+            return Err(if err.raw_os_error() == Some(0) {
+                io::Error::other("WM_COPYDATA not handled by target window")
+            } else {
+                err
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::util::WindowType;
+
+    #[test]
+    fn copy_data() {
+        let receiver = Window::new(
+            WindowType::MessageOnly,
+            RefCell::new(Vec::new()),
+            |received, msg| {
+                let data = unsafe { CopyData::from_message(&msg) }?;
+                received.borrow_mut().push(data.to_vec());
+                Some(1)
+            },
+        )
+        .unwrap();
+        let sender = Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap();
+
+        sender.send_copy_data(receiver.hwnd(), b"hello").unwrap();
+        sender.send_copy_data(receiver.hwnd(), &[]).unwrap();
+        assert_eq!(*receiver.state().borrow(), [b"hello".to_vec(), Vec::new()]);
+
+        // Not handled by the default window procedure.
+        assert!(receiver.send_copy_data(sender.hwnd(), b"hello").is_err());
+    }
+}
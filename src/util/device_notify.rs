@@ -0,0 +1,193 @@
+//! Device arrival and removal notifications, see [`register()`].
+
+use std::{
+    io, mem,
+    pin::Pin,
+    ptr, slice,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::{core::GUID, Win32::UI::WindowsAndMessaging::*};
+
+use super::{
+    channel, message_stream::Subscription, ChannelReceiver, Window, WindowType,
+    MESSAGE_STREAM_CAPACITY,
+};
+
+/// Kind of a [`DeviceEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEventKind {
+    /// `DBT_DEVICEARRIVAL`: The device was inserted and is available.
+    Arrival,
+
+    /// `DBT_DEVICEREMOVECOMPLETE`: The device was removed.
+    RemoveComplete,
+}
+
+/// Device interface arrival or removal received by a [`DeviceStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub kind: DeviceEventKind,
+    /// Device interface class, use `GUID::from_u128()` to convert back.
+    pub class: u128,
+    /// Device interface path, can be opened with `CreateFile()`.
+    pub path: String,
+}
+
+/// Stream of device interface notifications created with [`register()`].
+///
+/// Up to [`MESSAGE_STREAM_CAPACITY`] events are buffered while the stream is
+/// not polled, further events are dropped. Unregisters the notification when
+/// dropped.
+pub struct DeviceStream {
+    receiver: ChannelReceiver<DeviceEvent>,
+    _subscription: Option<Subscription>,
+    notify: HDEVNOTIFY,
+    _window: Window<()>,
+}
+
+/// Registers for arrival and removal notifications of device interfaces of
+/// the class `interface_class`, or of all classes when `None`.
+///
+/// Calls `RegisterDeviceNotificationW()` for a hidden [`WindowType::TopLevel`]
+/// window on the current thread. Message-only windows receive registered
+/// device interface notifications as well, but miss the `WM_DEVICECHANGE`
+/// broadcasts sent to top-level windows only. Broadcasts for other device
+/// types (volumes, ports, ...) are not part of the stream.
+pub fn register(interface_class: Option<GUID>) -> io::Result<DeviceStream> {
+    let window = Window::new(WindowType::TopLevel, (), |_, _| None)
+        .map_err(|_| io::Error::other("cannot create device notification window"))?;
+
+    // The event data must be read while `WM_DEVICECHANGE` is dispatched.
+    let (sender, receiver) = channel(MESSAGE_STREAM_CAPACITY);
+    let subscription = Subscription::new(window.hwnd(), move |msg| {
+        if msg.msg != WM_DEVICECHANGE {
+            return;
+        }
+        let kind = match msg.wparam as u32 {
+            DBT_DEVICEARRIVAL => DeviceEventKind::Arrival,
+            DBT_DEVICEREMOVECOMPLETE => DeviceEventKind::RemoveComplete,
+            _ => return,
+        };
+        if let Some(event) = unsafe { parse_event(kind, msg.lparam as *const DEV_BROADCAST_HDR) } {
+            // Drop events while the stream is full.
+            let _ = sender.send(event);
+        }
+    });
+
+    let mut filter: DEV_BROADCAST_DEVICEINTERFACE_W = unsafe { mem::zeroed() };
+    filter.dbcc_size = mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32;
+    filter.dbcc_devicetype = DBT_DEVTYP_DEVICEINTERFACE;
+    let mut flags = DEVICE_NOTIFY_WINDOW_HANDLE;
+    match interface_class {
+        Some(class) => filter.dbcc_classguid = class,
+        None => flags |= DEVICE_NOTIFY_ALL_INTERFACE_CLASSES,
+    }
+    let notify =
+        unsafe { RegisterDeviceNotificationW(window.hwnd(), ptr::from_ref(&filter).cast(), flags) };
+    if notify.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(DeviceStream {
+        receiver,
+        _subscription: subscription,
+        notify,
+        _window: window,
+    })
+}
+
+unsafe fn parse_event(
+    kind: DeviceEventKind,
+    header: *const DEV_BROADCAST_HDR,
+) -> Option<DeviceEvent> {
+    if header.is_null() || (*header).dbch_devicetype != DBT_DEVTYP_DEVICEINTERFACE {
+        return None;
+    }
+    let interface = header as *const DEV_BROADCAST_DEVICEINTERFACE_W;
+    let name_offset = mem::offset_of!(DEV_BROADCAST_DEVICEINTERFACE_W, dbcc_name);
+    let name_len = ((*interface).dbcc_size as usize).checked_sub(name_offset)? / 2;
+    let name = slice::from_raw_parts(
+        ptr::addr_of!((*interface).dbcc_name).cast::<u16>(),
+        name_len,
+    );
+    // The name is nul terminated within the reported size.
+    let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+    let class = (*interface).dbcc_classguid;
+    Some(DeviceEvent {
+        kind,
+        class: guid_to_u128(&class),
+        path: String::from_utf16_lossy(name),
+    })
+}
+
+fn guid_to_u128(guid: &GUID) -> u128 {
+    (guid.data1 as u128) << 96
+        | (guid.data2 as u128) << 80
+        | (guid.data3 as u128) << 64
+        | u64::from_be_bytes(guid.data4) as u128
+}
+
+impl DeviceStream {
+    /// Receives the next event, `None` when the window was destroyed.
+    pub async fn recv(&mut self) -> Option<DeviceEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for DeviceStream {
+    fn drop(&mut self) {
+        unsafe { UnregisterDeviceNotification(self.notify) };
+    }
+}
+
+impl Stream for DeviceStream {
+    type Item = DeviceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DeviceEvent>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::poll_ready;
+
+    // GUID_DEVINTERFACE_USB_DEVICE
+    const USB_DEVICE: u128 = 0xA5DCBF10_6530_11D2_901F_00C04FB951ED;
+
+    #[test]
+    fn guid_round_trip() {
+        assert_eq!(guid_to_u128(&GUID::from_u128(USB_DEVICE)), USB_DEVICE);
+    }
+
+    #[test]
+    fn device_arrival() {
+        let mut stream = register(Some(GUID::from_u128(USB_DEVICE))).unwrap();
+
+        // Emulate a notification with a nul terminated path.
+        let path: Vec<u16> = r"\\?\USB#VID_1234".encode_utf16().chain([0]).collect();
+        let name_offset = mem::offset_of!(DEV_BROADCAST_DEVICEINTERFACE_W, dbcc_name);
+        let size = name_offset + path.len() * 2;
+        let mut buffer = vec![0u64; size.div_ceil(8)];
+        let interface = buffer.as_mut_ptr() as *mut DEV_BROADCAST_DEVICEINTERFACE_W;
+        unsafe {
+            (*interface).dbcc_size = size as u32;
+            (*interface).dbcc_devicetype = DBT_DEVTYP_DEVICEINTERFACE;
+            (*interface).dbcc_classguid = GUID::from_u128(USB_DEVICE);
+            let name = ptr::addr_of_mut!((*interface).dbcc_name).cast::<u16>();
+            ptr::copy_nonoverlapping(path.as_ptr(), name, path.len());
+            stream._window.send(
+                WM_DEVICECHANGE,
+                DBT_DEVICEARRIVAL as usize,
+                interface as isize,
+            );
+        }
+
+        let event = poll_ready(stream.recv()).unwrap().unwrap();
+        assert_eq!(event.kind, DeviceEventKind::Arrival);
+        assert_eq!(event.class, USB_DEVICE);
+        assert_eq!(event.path, r"\\?\USB#VID_1234");
+    }
+}
@@ -0,0 +1,95 @@
+//! System-wide hotkeys, see [`register()`].
+
+use std::{
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::UI::{
+    Input::KeyboardAndMouse::{RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS},
+    WindowsAndMessaging::WM_HOTKEY,
+};
+
+use super::{MessageStream, Window, WindowMessage, WindowType};
+
+// Each hotkey has its own window, the id only needs to be unique per window.
+const HOTKEY_ID: i32 = 0;
+
+/// Stream of the `WM_HOTKEY` messages of a hotkey created with [`register()`].
+///
+/// Unregisters the hotkey when dropped.
+pub struct HotkeyStream {
+    stream: MessageStream,
+    window: Window<()>,
+}
+
+/// Registers a system-wide hotkey.
+///
+/// `modifiers` is a combination of the `MOD_*` flags and `vk` the virtual key
+/// code. A hidden message-only window on the current thread receives the
+/// `WM_HOTKEY` messages, awaited from the returned stream. Fails when the
+/// hotkey is registered already, e.g. by another application.
+pub fn register(modifiers: HOT_KEY_MODIFIERS, vk: u32) -> io::Result<HotkeyStream> {
+    let window = Window::new(WindowType::MessageOnly, (), |_, _| None)
+        .map_err(|_| io::Error::other("cannot create hotkey window"))?;
+    let stream = window.messages(|msg| msg == WM_HOTKEY);
+    if unsafe { RegisterHotKey(window.hwnd(), HOTKEY_ID, modifiers, vk) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(HotkeyStream { stream, window })
+}
+
+impl HotkeyStream {
+    /// Waits for the next hotkey press.
+    pub async fn pressed(&mut self) -> Option<WindowMessage> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Drop for HotkeyStream {
+    fn drop(&mut self) {
+        unsafe { UnregisterHotKey(self.window.hwnd(), HOTKEY_ID) };
+    }
+}
+
+impl Stream for HotkeyStream {
+    type Item = WindowMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WindowMessage>> {
+        Pin::new(&mut self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::*;
+
+    use super::*;
+    use crate::block_on;
+
+    const MODIFIERS: HOT_KEY_MODIFIERS = MOD_CONTROL | MOD_ALT | MOD_SHIFT | MOD_NOREPEAT;
+
+    #[test]
+    fn register_hotkey() {
+        let mut hotkey = register(MODIFIERS, VK_F24.into()).unwrap();
+        // Registered already.
+        assert!(register(MODIFIERS, VK_F24.into()).is_err());
+
+        // Emulate a key press.
+        unsafe {
+            hotkey
+                .window
+                .post(WM_HOTKEY, HOTKEY_ID as usize, 0)
+                .unwrap()
+        };
+        let msg = block_on(hotkey.pressed()).unwrap();
+        assert_eq!(msg.wparam, HOTKEY_ID as usize);
+
+        // Unregistered when dropped.
+        drop(hotkey);
+        register(MODIFIERS, VK_F24.into()).unwrap();
+    }
+}
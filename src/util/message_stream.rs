@@ -0,0 +1,397 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    Foundation::*,
+    UI::{
+        Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass},
+        WindowsAndMessaging::{WM_NCDESTROY, WM_TIMER},
+    },
+};
+
+use super::{Window, WindowCreationError, WindowMessage, WindowType};
+
+/// Number of messages buffered by a stream created with [`Window::messages()`].
+pub const MESSAGE_STREAM_CAPACITY: usize = 64;
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<WindowMessage>,
+    capacity: usize,
+    waker: Option<Waker>,
+    stream_alive: bool,
+    window_alive: bool,
+}
+
+// Owned by the `wndproc` closure: Dropped together with the window user data.
+struct QueueSender(Rc<RefCell<Queue>>);
+
+impl QueueSender {
+    fn push(&self, msg: WindowMessage) {
+        let waker = {
+            let mut queue = self.0.borrow_mut();
+            if !queue.stream_alive || queue.messages.len() >= queue.capacity {
+                return;
+            }
+            queue.messages.push_back(msg);
+            queue.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        let waker = {
+            let mut queue = self.0.borrow_mut();
+            queue.window_alive = false;
+            queue.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// Subclass reference data of a `Subscription`.
+struct Subscriber {
+    handler: Box<dyn Fn(&WindowMessage)>,
+    attached: Rc<Cell<bool>>,
+}
+
+// Calls a handler for all messages of a window, before its window procedure.
+// The handler is dropped when the subscription is dropped or when the window
+// is destroyed, whichever happens first.
+pub(super) struct Subscription {
+    hwnd: HWND,
+    subscriber: *mut Subscriber,
+    attached: Rc<Cell<bool>>,
+}
+
+impl Subscription {
+    // Returns `None` when the window belongs to another thread.
+    pub(super) fn new(hwnd: HWND, handler: impl Fn(&WindowMessage) + 'static) -> Option<Self> {
+        let attached = Rc::new(Cell::new(true));
+        let subscriber = Box::into_raw(Box::new(Subscriber {
+            handler: Box::new(handler),
+            attached: attached.clone(),
+        }));
+        // The subscriber address identifies the subclass, which allows
+        // multiple subscriptions per window.
+        let id = subscriber as usize;
+        if unsafe { SetWindowSubclass(hwnd, Some(subscriber_proc), id, id) } == 0 {
+            drop(unsafe { Box::from_raw(subscriber) });
+            return None;
+        }
+        Some(Self {
+            hwnd,
+            subscriber,
+            attached,
+        })
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Otherwise the subscriber was dropped in `WM_NCDESTROY` already.
+        if self.attached.get() {
+            unsafe {
+                RemoveWindowSubclass(self.hwnd, Some(subscriber_proc), self.subscriber as usize);
+                drop(Box::from_raw(self.subscriber));
+            }
+        }
+    }
+}
+
+unsafe extern "system" fn subscriber_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    subclass_id: usize,
+    ref_data: usize,
+) -> LRESULT {
+    let subscriber = ref_data as *mut Subscriber;
+    if msg == WM_NCDESTROY {
+        RemoveWindowSubclass(hwnd, Some(subscriber_proc), subclass_id);
+        (*subscriber).attached.set(false);
+        drop(Box::from_raw(subscriber));
+    } else {
+        ((*subscriber).handler)(&WindowMessage {
+            hwnd,
+            msg,
+            wparam,
+            lparam,
+        });
+    }
+    DefSubclassProc(hwnd, msg, wparam, lparam)
+}
+
+/// Stream of messages received by a window, created with
+/// [`Window::with_channel()`] or [`Window::messages()`].
+///
+/// Ends after the window was destroyed and all buffered messages were
+/// received. Dropping the stream stops buffering of messages.
+pub struct MessageStream {
+    queue: Rc<RefCell<Queue>>,
+    // Of a stream created with `Window::messages()`.
+    _subscription: Option<Subscription>,
+}
+
+impl Drop for MessageStream {
+    fn drop(&mut self) {
+        let mut queue = self.queue.borrow_mut();
+        queue.stream_alive = false;
+        queue.messages.clear();
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = WindowMessage;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.borrow_mut();
+        if let Some(msg) = queue.messages.pop_front() {
+            Poll::Ready(Some(msg))
+        } else if !queue.window_alive {
+            Poll::Ready(None)
+        } else {
+            match &mut queue.waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                waker => *waker = Some(cx.waker().clone()),
+            }
+            Poll::Pending
+        }
+    }
+}
+
+impl Window<()> {
+    /// Creates a new window which forwards messages to a [`MessageStream`].
+    ///
+    /// Messages for which `filter` returns `true` are buffered until received
+    /// from the stream. All messages, captured or not, are handled by the
+    /// default window procedure because a task receives them asynchronously.
+    /// Use [`Window::new()`] when the result of a message matters.
+    pub fn with_channel<F>(
+        window_type: WindowType,
+        filter: F,
+    ) -> Result<(Self, MessageStream), WindowCreationError>
+    where
+        F: Fn(&WindowMessage) -> bool + 'static,
+    {
+        let queue = new_queue(usize::MAX);
+        let sender = QueueSender(queue.clone());
+        let window = Window::new(window_type, (), move |_, msg| {
+            if filter(&msg) {
+                sender.push(msg);
+            }
+            None
+        })?;
+        Ok((
+            window,
+            MessageStream {
+                queue,
+                _subscription: None,
+            },
+        ))
+    }
+}
+
+impl<S> Window<S> {
+    /// Returns a stream of the messages with an id for which `filter` returns
+    /// `true`.
+    ///
+    /// Useful to `.await` specific messages, e.g. `WM_COMMAND` of a menu item,
+    /// instead of handling them in the `wndproc` closure. The messages are
+    /// observed before the `wndproc` closure runs, which still handles them.
+    /// Up to [`MESSAGE_STREAM_CAPACITY`] messages are buffered while the stream
+    /// is not polled, further messages are not added to the stream.
+    ///
+    /// Multiple streams can be created for the same window. Each stream stops
+    /// buffering when dropped.
+    pub fn messages(&self, filter: impl Fn(u32) -> bool + 'static) -> MessageStream {
+        let queue = new_queue(MESSAGE_STREAM_CAPACITY);
+        let sender = QueueSender(queue.clone());
+        // Without a subscription the sender is dropped, which ends the stream.
+        // Cannot happen for windows of the current thread.
+        let subscription = Subscription::new(self.hwnd(), move |msg| {
+            if filter(msg.msg) {
+                sender.push(msg.clone());
+            }
+        });
+        MessageStream {
+            queue,
+            _subscription: subscription,
+        }
+    }
+
+    /// Waits for the next message for which `pred` returns `true`, `None`
+    /// when the window is destroyed first.
+    ///
+    /// Captures messages dispatched after this call, before the `wndproc`
+    /// closure handles them, until the first match. Useful for state machine
+    /// style handling, e.g. await a button press and then its release.
+    /// Overlapping calls do not interfere: Each one waits for its own match.
+    pub fn next_message(
+        &self,
+        pred: impl Fn(&WindowMessage) -> bool + 'static,
+    ) -> impl Future<Output = Option<WindowMessage>> {
+        let queue = new_queue(1);
+        let sender = QueueSender(queue.clone());
+        // Removed when the returned future completes or is dropped.
+        let subscription = Subscription::new(self.hwnd(), move |msg| {
+            if pred(msg) {
+                sender.push(msg.clone());
+            }
+        });
+        let mut stream = MessageStream {
+            queue,
+            _subscription: subscription,
+        };
+        async move { poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await }
+    }
+
+    /// Waits for the next `WM_TIMER` message of the timer `timer_id`, `false`
+    /// when the window is destroyed first.
+    ///
+    /// For timers of this window created with `SetTimer()` by other code. The
+    /// timer is only observed and never killed: A periodic timer keeps firing
+    /// and the `wndproc` closure still receives its messages.
+    pub fn await_timer(&self, timer_id: usize) -> impl Future<Output = bool> {
+        let timer = self.next_message(move |msg| msg.msg == WM_TIMER && msg.wparam == timer_id);
+        async move { timer.await.is_some() }
+    }
+}
+
+fn new_queue(capacity: usize) -> Rc<RefCell<Queue>> {
+    Rc::new(RefCell::new(Queue {
+        capacity,
+        stream_alive: true,
+        window_alive: true,
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use windows_sys::Win32::UI::WindowsAndMessaging::*;
+
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn receive_messages() {
+        let (w, mut stream) =
+            Window::with_channel(WindowType::MessageOnly, |msg| msg.msg == WM_USER).unwrap();
+        block_on(async {
+            for i in 0..3 {
+                unsafe { w.post(WM_USER + 1, 0, 0).unwrap() };
+                unsafe { w.post(WM_USER, i, 0).unwrap() };
+            }
+            for i in 0..3 {
+                let msg = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+                assert_eq!(msg.unwrap().wparam, i);
+            }
+        });
+
+        // Stream ends when the window is destroyed.
+        drop(w);
+        assert!(
+            crate::poll_ready(poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn stream_of_existing_window() {
+        let handled = Rc::new(Cell::new(0));
+        let w = Window::new(WindowType::MessageOnly, handled.clone(), |handled, msg| {
+            (msg.msg == WM_COMMAND).then(|| {
+                handled.set(handled.get() + 1);
+                0
+            })
+        })
+        .unwrap();
+        let mut commands = w.messages(|msg| msg == WM_COMMAND);
+        fn next(stream: &mut MessageStream) -> impl Future<Output = Option<WindowMessage>> + '_ {
+            poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx))
+        }
+
+        // Buffered while not polled, up to the capacity.
+        for i in 0..MESSAGE_STREAM_CAPACITY + 1 {
+            unsafe { w.send(WM_USER, 0, 0) };
+            unsafe { w.send(WM_COMMAND, i, 0) };
+        }
+        assert_eq!(handled.get(), MESSAGE_STREAM_CAPACITY + 1);
+        block_on(async {
+            for i in 0..MESSAGE_STREAM_CAPACITY {
+                assert_eq!(next(&mut commands).await.unwrap().wparam, i);
+            }
+        });
+        assert!(crate::poll_ready(next(&mut commands)).is_err());
+
+        // Ends when the window is destroyed.
+        drop(w);
+        assert!(crate::poll_ready(next(&mut commands)).unwrap().is_none());
+    }
+
+    #[test]
+    fn await_next_message() {
+        let w = Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap();
+        let mut press = Box::pin(w.next_message(|msg| msg.msg == WM_USER));
+        let mut release = Box::pin(w.next_message(|msg| msg.msg == WM_USER + 1));
+        let mut any = Box::pin(w.next_message(|_| true));
+
+        unsafe {
+            w.send(WM_USER + 1, 1, 0);
+            w.send(WM_USER, 2, 0);
+            w.send(WM_USER + 1, 3, 0);
+        }
+        // Each call got the first match since it was made.
+        assert_eq!(
+            crate::poll_ready(press.as_mut()).unwrap().unwrap().wparam,
+            2
+        );
+        assert_eq!(
+            crate::poll_ready(release.as_mut()).unwrap().unwrap().wparam,
+            1
+        );
+        assert_eq!(crate::poll_ready(any.as_mut()).unwrap().unwrap().wparam, 1);
+
+        let pending = w.next_message(|_| true);
+        drop(w);
+        assert!(crate::poll_ready(pending).unwrap().is_none());
+    }
+
+    #[test]
+    fn await_user_timer() {
+        let w = Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap();
+        unsafe {
+            SetTimer(w.hwnd(), 1, 50, None);
+            SetTimer(w.hwnd(), 2, 10, None);
+        }
+        block_on(async {
+            assert!(w.await_timer(2).await);
+            assert!(w.await_timer(1).await);
+            // Still running, periodic timers fire again.
+            assert!(w.await_timer(2).await);
+        });
+        assert_ne!(unsafe { KillTimer(w.hwnd(), 1) }, 0);
+
+        let pending = w.await_timer(2);
+        drop(w);
+        assert!(!block_on(pending));
+    }
+}
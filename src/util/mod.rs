@@ -1,7 +1,54 @@
 //! Helper code to work with windows.
 
 mod msg_filter_hook;
-pub(crate) use msg_filter_hook::*;
+pub use msg_filter_hook::with_msg_filter;
+pub(crate) use msg_filter_hook::MsgFilterHook;
+
+mod async_mutex;
+pub use async_mutex::*;
+
+mod broadcast;
+pub use broadcast::*;
+
+mod cancellation_token;
+pub use cancellation_token::*;
+
+mod channel;
+pub use channel::*;
+
+pub mod clipboard;
+
+mod copy_data;
+pub use copy_data::*;
+
+pub mod device_notify;
+
+pub mod hotkey;
+
+mod message_stream;
+pub use message_stream::*;
+
+pub mod named_pipe;
+
+pub mod raw_input;
+
+pub mod socket;
+
+pub mod oneshot;
+pub use oneshot::oneshot;
+
+pub mod overlapped;
+
+mod paint;
+pub use paint::*;
+
+pub mod power;
+
+mod wait;
+pub use wait::*;
 
 mod window;
 pub use window::*;
+
+mod window_group;
+pub use window_group::*;
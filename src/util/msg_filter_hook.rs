@@ -1,14 +1,74 @@
-use std::{cell::Cell, marker::PhantomData, ptr};
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
 use windows_sys::Win32::{
     Foundation::*, System::Threading::GetCurrentThreadId, UI::WindowsAndMessaging::*,
 };
 
+/// Calls `handler` for messages of modal loops while `body` runs.
+///
+/// Installs a [`WH_MSGFILTER`] hook, which is called when a modal window
+/// (menu, dialog box, scroll bar, ...) runs its own message loop or when
+/// `CallMsgFilter()` is called. Return `true` from `handler` to consume the
+/// message, it is then not dispatched. The hook is unregistered before this
+/// function returns or unwinds, so `handler` may borrow local variables.
+///
+/// Typically `body` runs a message loop, like [`block_on()`](crate::block_on).
+/// Nested calls, also the ones made by [`MessageLoop::run`](crate::MessageLoop::run),
+/// are stacked: The innermost handler sees a message first and the outer
+/// handlers are called until one consumes it.
+///
+/// A handler cannot end a modal loop on its own. To do so, post a `WM_QUIT`
+/// message with `PostQuitMessage()`: It ends the modal loop and then the
+/// next message loop in `body`, which must be prepared for it: A nested
+/// [`block_on()`](crate::block_on) returns and forwards the quit message to
+/// the outer loop, the outermost one panics when its future is not done.
+///
+/// A panic of `handler` is caught and resumed once `body` returns.
+///
+/// [`WH_MSGFILTER`]: https://learn.microsoft.com/en-us/windows/win32/winmsg/about-hooks#wh_msgfilter-and-wh_sysmsgfilter
+pub fn with_msg_filter<R>(handler: impl Fn(&MSG) -> bool, body: impl FnOnce() -> R) -> R {
+    let panic_payload = Cell::new(None);
+    let result = {
+        // SAFETY: The hook is dropped at the end of scope, it is not leaked.
+        let _hook = unsafe {
+            MsgFilterHook::register(|msg| {
+                panic::catch_unwind(AssertUnwindSafe(|| handler(msg))).unwrap_or_else(|payload| {
+                    panic_payload.set(Some(payload));
+                    false
+                })
+            })
+        };
+        body()
+    };
+    if let Some(payload) = panic_payload.take() {
+        panic::resume_unwind(payload);
+    }
+    result
+}
+
+// Type erased handler closure.
+#[derive(Clone, Copy)]
+struct Handler {
+    closure: *const (),
+    call: unsafe fn(*const (), &MSG) -> bool,
+    // Registered for the `WH_GETMESSAGE` hook instead of `WH_MSGFILTER`.
+    get_message: bool,
+}
+
 thread_local! {
-    static MSG_FILTER_HOOK: Cell<*mut ()> = const { Cell::new(ptr::null_mut()) };
+    // A single hook of each type serves all nested registrations. Handlers are
+    // stored in registration order, the innermost one is called first.
+    static MSG_FILTER_HOOK: Cell<HHOOK> = const { Cell::new(ptr::null_mut()) };
+    static GET_MESSAGE_HOOK: Cell<HHOOK> = const { Cell::new(ptr::null_mut()) };
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(Vec::new()) };
 }
 
 pub struct MsgFilterHook<'a, F> {
-    handle: HHOOK,
+    closure: *mut F,
     _lifetime_and_type: PhantomData<&'a F>,
 }
 
@@ -16,23 +76,58 @@ impl<'a, F> MsgFilterHook<'a, F>
 where
     F: Fn(&MSG) -> bool + 'a,
 {
+    /// Registers a handler, stacked on top of the handlers registered already.
+    ///
+    /// Handlers must be dropped in reverse order of registration.
+    ///
     /// # Safety
     ///
     /// This function is safe as long as the returned handle is not leaked
     /// or if the provided handler closure is `'static`.
     pub unsafe fn register(handler: F) -> Self {
-        assert!(MSG_FILTER_HOOK.get().is_null());
+        Self::register_impl(handler, false)
+    }
+
+    /// Like [`MsgFilterHook::register`], but uses a `WH_GETMESSAGE` hook.
+    ///
+    /// The handler sees every message removed from the queue of the thread,
+    /// by any message loop, modal or not. Consumed messages are replaced with
+    /// `WM_NULL` before the message loop dispatches them. Handlers registered
+    /// this way are not called by the `WH_MSGFILTER` hook, so a message is
+    /// passed to each handler once only.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`MsgFilterHook::register`].
+    pub unsafe fn register_get_message(handler: F) -> Self {
+        Self::register_impl(handler, true)
+    }
 
-        MSG_FILTER_HOOK.set(Box::into_raw(Box::new(handler)) as *mut ());
+    unsafe fn register_impl(handler: F, get_message: bool) -> Self {
+        let closure = Box::into_raw(Box::new(handler));
+        HANDLERS.with_borrow_mut(|handlers| {
+            handlers.push(Handler {
+                closure: closure as *const (),
+                call: call_handler::<F>,
+                get_message,
+            })
+        });
 
-        let handle = SetWindowsHookExA(
-            WH_MSGFILTER,
-            Some(hook_proc::<F>),
-            ptr::null_mut(),
-            GetCurrentThreadId(),
-        );
+        let (hook, id, hook_proc): (_, _, HOOKPROC) = if get_message {
+            (&GET_MESSAGE_HOOK, WH_GETMESSAGE, Some(get_message_proc))
+        } else {
+            (&MSG_FILTER_HOOK, WH_MSGFILTER, Some(msg_filter_proc))
+        };
+        if hook.get().is_null() {
+            hook.set(SetWindowsHookExA(
+                id,
+                hook_proc,
+                ptr::null_mut(),
+                GetCurrentThreadId(),
+            ));
+        }
         Self {
-            handle,
+            closure,
             _lifetime_and_type: PhantomData,
         }
     }
@@ -40,24 +135,129 @@ where
 
 impl<F> Drop for MsgFilterHook<'_, F> {
     fn drop(&mut self) {
+        let handler = HANDLERS.with_borrow_mut(|handlers| handlers.pop().unwrap());
+        assert_eq!(
+            handler.closure, self.closure as *const (),
+            "message filter hooks must be dropped in reverse order of registration"
+        );
+        let hook = if handler.get_message {
+            &GET_MESSAGE_HOOK
+        } else {
+            &MSG_FILTER_HOOK
+        };
+        let last_of_type = HANDLERS.with_borrow(|handlers| {
+            !handlers
+                .iter()
+                .any(|h| h.get_message == handler.get_message)
+        });
         unsafe {
-            UnhookWindowsHookEx(self.handle);
-            drop(Box::from_raw(
-                MSG_FILTER_HOOK.replace(ptr::null_mut()) as *mut F
-            ));
+            if last_of_type {
+                UnhookWindowsHookEx(hook.replace(ptr::null_mut()));
+            }
+            drop(Box::from_raw(self.closure));
         }
     }
 }
 
-unsafe extern "system" fn hook_proc<F>(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT
+unsafe fn call_handler<F>(closure: *const (), msg: &MSG) -> bool
 where
     F: Fn(&MSG) -> bool,
 {
-    let f = &*(MSG_FILTER_HOOK.get() as *mut F);
-    let msg = &*(lparam as *const MSG);
-    if f(msg) {
+    (*(closure as *const F))(msg)
+}
+
+// Returns `true` when a handler consumed the message.
+unsafe fn call_handlers(msg: &MSG, get_message: bool) -> bool {
+    // Chain from the innermost to the outermost handler until one consumes
+    // the message. Handlers are looked up one by one because a handler may
+    // register (and unregister) nested handlers while running.
+    let mut i = HANDLERS.with_borrow(Vec::len);
+    while i > 0 {
+        i -= 1;
+        let handler = HANDLERS.with_borrow(|handlers| handlers[i]);
+        if handler.get_message == get_message && (handler.call)(handler.closure, msg) {
+            return true;
+        }
+    }
+    false
+}
+
+unsafe extern "system" fn msg_filter_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // Negative codes must be passed on without processing.
+    if code >= 0 && call_handlers(&*(lparam as *const MSG), false) {
         1
     } else {
         CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
     }
 }
+
+unsafe extern "system" fn get_message_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    // Messages only peeked at are seen again when they are removed.
+    if code == HC_ACTION as i32 && wparam == PM_REMOVE as usize {
+        let msg = &mut *(lparam as *mut MSG);
+        if call_handlers(msg, true) {
+            msg.message = WM_NULL;
+        }
+    }
+    CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::*;
+
+    fn call_msg_filter(message: u32) -> bool {
+        let msg = MSG {
+            message,
+            ..unsafe { mem::zeroed() }
+        };
+        unsafe { CallMsgFilterW(&msg, 0) != 0 }
+    }
+
+    #[test]
+    fn scoped_msg_filter() {
+        let seen = Cell::new(0);
+        let consumed = with_msg_filter(
+            |msg| {
+                seen.set(msg.message);
+                true
+            },
+            || call_msg_filter(WM_USER),
+        );
+        assert!(consumed);
+        assert_eq!(seen.get(), WM_USER);
+
+        // Unregistered after returning.
+        assert!(!call_msg_filter(WM_USER + 1));
+        assert_eq!(seen.get(), WM_USER);
+    }
+
+    #[test]
+    fn nested_msg_filters() {
+        let order = RefCell::new(Vec::new());
+        with_msg_filter(
+            |_| {
+                order.borrow_mut().push("outer");
+                true
+            },
+            || {
+                with_msg_filter(
+                    |_| {
+                        order.borrow_mut().push("inner");
+                        false
+                    },
+                    || call_msg_filter(WM_USER),
+                )
+            },
+        );
+        assert_eq!(*order.borrow(), ["inner", "outer"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_in_msg_filter() {
+        with_msg_filter(|_| panic!(), || call_msg_filter(WM_USER));
+    }
+}
@@ -0,0 +1,234 @@
+//! Server end of named pipes with async connect, read and write.
+
+use std::{io, mem, ptr};
+
+use windows_sys::Win32::{
+    Foundation::*,
+    Storage::FileSystem::*,
+    System::{
+        Pipes::*,
+        Threading::CreateEventA,
+        IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+    },
+};
+
+use super::overlapped::await_event;
+
+const BUFFER_SIZE: u32 = 4096;
+
+/// Server end of a named pipe instance, closed when dropped.
+///
+/// Each instance serves one client at a time. To serve multiple clients
+/// concurrently, create an instance with the same name for each of them.
+///
+/// Operations are overlapped I/O on the pipe handle, awaited with
+/// [`await_event()`]. Dropping the future of an operation before it completed
+/// cancels the operation and blocks until the cancellation is done, so that
+/// the buffer can be released.
+pub struct NamedPipeServer {
+    pipe: HANDLE,
+    overlapped: OVERLAPPED,
+}
+
+impl NamedPipeServer {
+    /// Creates a byte mode pipe instance, `name` has the form
+    /// `\\.\pipe\pipename`.
+    ///
+    /// Remote clients are rejected.
+    pub fn create(name: &str) -> io::Result<Self> {
+        let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                ptr::null(),
+            )
+        };
+        if pipe == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let event = unsafe { CreateEventA(ptr::null(), TRUE, FALSE, ptr::null()) };
+        if event.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { CloseHandle(pipe) };
+            return Err(err);
+        }
+        let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+        overlapped.hEvent = event;
+        Ok(Self { pipe, overlapped })
+    }
+
+    /// Waits for a client to connect.
+    ///
+    /// Returns right away when a client connected since the instance was
+    /// created or disconnected.
+    pub async fn connect(&mut self) -> io::Result<()> {
+        let pipe = self.pipe;
+        let result = self
+            .complete(|overlapped| unsafe { ConnectNamedPipe(pipe, overlapped) })
+            .await;
+        match result {
+            // Connected before the call, the event is not signaled then.
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Disconnects the client, the instance can be connected again afterwards.
+    ///
+    /// Unread data of the client is discarded.
+    pub fn disconnect(&mut self) -> io::Result<()> {
+        if unsafe { DisconnectNamedPipe(self.pipe) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Reads into `buf` and returns the number of bytes read, 0 when the
+    /// client closed its end of the pipe.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pipe = self.pipe;
+        let len = buf.len().try_into().unwrap_or(u32::MAX);
+        let result = self
+            .complete(|overlapped| unsafe {
+                ReadFile(pipe, buf.as_mut_ptr(), len, ptr::null_mut(), overlapped)
+            })
+            .await;
+        match result {
+            Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+            result => result.map(|read| read as usize),
+        }
+    }
+
+    /// Writes from `buf` and returns the number of bytes written.
+    pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = self.pipe;
+        let len = buf.len().try_into().unwrap_or(u32::MAX);
+        let result = self
+            .complete(|overlapped| unsafe {
+                WriteFile(pipe, buf.as_ptr(), len, ptr::null_mut(), overlapped)
+            })
+            .await;
+        result.map(|written| written as usize)
+    }
+
+    /// Writes all of `buf`.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let written = self.write(buf).await?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+
+    // Starts an overlapped operation and awaits its completion.
+    async fn complete(&mut self, start: impl FnOnce(*mut OVERLAPPED) -> BOOL) -> io::Result<u32> {
+        if start(&mut self.overlapped) == 0 {
+            let err = unsafe { GetLastError() };
+            if err != ERROR_IO_PENDING {
+                return Err(io::Error::from_raw_os_error(err as i32));
+            }
+        }
+        let pending = PendingOperation {
+            pipe: self.pipe,
+            overlapped: &mut self.overlapped,
+        };
+        // SAFETY: `pending` cancels the operation when the future is dropped.
+        let result = unsafe { await_event(pending.pipe, &mut *pending.overlapped) }.await;
+        // On errors the operation might still be pending, cancel it to be sure.
+        if result.is_ok() {
+            mem::forget(pending);
+        }
+        result
+    }
+}
+
+impl Drop for NamedPipeServer {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.pipe);
+            CloseHandle(self.overlapped.hEvent);
+        }
+    }
+}
+
+// Cancels the operation when dropped before completion.
+struct PendingOperation {
+    pipe: HANDLE,
+    overlapped: *mut OVERLAPPED,
+}
+
+impl Drop for PendingOperation {
+    fn drop(&mut self) {
+        let mut transferred = 0;
+        unsafe {
+            CancelIoEx(self.pipe, self.overlapped);
+            GetOverlappedResult(self.pipe, self.overlapped, &mut transferred, TRUE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Write},
+        thread,
+    };
+
+    use super::*;
+    use crate::{block_on, combinator::race, yield_now};
+
+    #[test]
+    fn echo() {
+        let name = r"\\.\pipe\winmsg-executor-echo";
+        let mut server = NamedPipeServer::create(name).unwrap();
+        let client = thread::spawn(move || {
+            let mut pipe = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(name)
+                .unwrap();
+            pipe.write_all(b"ping").unwrap();
+            let mut reply = [0; 4];
+            pipe.read_exact(&mut reply).unwrap();
+            reply
+        });
+
+        block_on(async {
+            server.connect().await.unwrap();
+            let mut buf = [0; 16];
+            let read = server.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..read], b"ping");
+            server.write_all(b"pong").await.unwrap();
+            // The client closes its end after reading the reply.
+            assert_eq!(server.read(&mut buf).await.unwrap(), 0);
+        });
+        assert_eq!(&client.join().unwrap(), b"pong");
+    }
+
+    #[test]
+    fn connected_before_connect() {
+        let name = r"\\.\pipe\winmsg-executor-connected";
+        let mut server = NamedPipeServer::create(name).unwrap();
+        let _client = OpenOptions::new().read(true).open(name).unwrap();
+        block_on(server.connect()).unwrap();
+    }
+
+    #[test]
+    fn cancel_read() {
+        let name = r"\\.\pipe\winmsg-executor-cancel";
+        let mut server = NamedPipeServer::create(name).unwrap();
+        let _client = OpenOptions::new().write(true).open(name).unwrap();
+        block_on(async {
+            server.connect().await.unwrap();
+            let mut buf = [0; 16];
+            race(server.read(&mut buf), yield_now()).await;
+        });
+    }
+}
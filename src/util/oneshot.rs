@@ -0,0 +1,147 @@
+//! Channel to send a single value to a task, see [`oneshot()`].
+
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+struct State<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+/// Error returned by [`Receiver`] when the sender was dropped without
+/// sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("oneshot sender dropped without sending")
+    }
+}
+
+impl Error for Canceled {}
+
+/// Sending half of a channel created by [`oneshot()`].
+pub struct Sender<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// Receiving half of a channel created by [`oneshot()`].
+///
+/// Awaiting it returns the value sent or [`Canceled`] when the sender was
+/// dropped without sending.
+pub struct Receiver<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// Creates a channel to send a single value to a task, possibly across
+/// threads.
+///
+/// Sending wakes the receiving task, which posts a wake message to the message
+/// loop of its thread. The receiver is typically awaited on the thread that
+/// created the channel while the sender is moved to a worker thread.
+pub fn oneshot<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(Mutex::new(State {
+        value: None,
+        waker: None,
+        sender_alive: true,
+        receiver_alive: true,
+    }));
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends the value to the receiver.
+    ///
+    /// Returns the value back when the receiver was dropped already.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut state = self.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(value);
+        }
+        // The receiver is woken when `self` is dropped at the end of scope.
+        state.value = Some(value);
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.sender_alive = false;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().receiver_alive = false;
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(value) = state.value.take() {
+            Poll::Ready(Ok(value))
+        } else if !state.sender_alive {
+            Poll::Ready(Err(Canceled))
+        } else {
+            match &mut state.waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                waker => *waker = Some(cx.waker().clone()),
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn send_from_other_thread() {
+        let (tx, rx) = oneshot();
+        let worker = thread::spawn(move || tx.send(42).unwrap());
+        assert_eq!(block_on(rx), Ok(42));
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn sender_dropped() {
+        let (tx, rx) = oneshot::<()>();
+        thread::spawn(move || drop(tx)).join().unwrap();
+        assert_eq!(block_on(rx), Err(Canceled));
+    }
+
+    #[test]
+    fn receiver_dropped() {
+        let (tx, rx) = oneshot();
+        drop(rx);
+        assert_eq!(tx.send(42), Err(42));
+    }
+}
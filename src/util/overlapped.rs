@@ -0,0 +1,447 @@
+//! Awaiting overlapped I/O, see [`await_event()`] and [`Reactor`].
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    future::Future,
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    ptr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use windows_sys::Win32::{
+    Foundation::*,
+    System::{
+        Threading::INFINITE,
+        IO::{
+            CreateIoCompletionPort, GetOverlappedResult, GetQueuedCompletionStatus,
+            PostQueuedCompletionStatus, OVERLAPPED,
+        },
+    },
+};
+
+use super::wait_for_handle;
+
+/// Waits for an overlapped I/O operation on `file` to complete and returns
+/// the number of bytes transferred.
+///
+/// Call it after starting the operation (`ReadFile()`, `WriteFile()`, ...)
+/// with an `OVERLAPPED` structure whose `hEvent` is a manual-reset event.
+/// The event is awaited with [`wait_for_handle()`], then the result is
+/// queried with [`GetOverlappedResult()`]. It is fine to call this function
+/// when the operation completed synchronously, the event is signaled then.
+///
+/// # Safety
+///
+/// The caller owns the `OVERLAPPED` structure and the buffer of the
+/// operation. Both must stay alive until the operation completed, also when
+/// the future is dropped before: Cancel the operation with `CancelIoEx()`
+/// and wait for the cancellation to complete before releasing them.
+///
+/// [`GetOverlappedResult()`]: https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-getoverlappedresult
+pub async unsafe fn await_event(file: HANDLE, overlapped: &mut OVERLAPPED) -> io::Result<u32> {
+    wait_for_handle(overlapped.hEvent).await?;
+    let mut transferred = 0;
+    if GetOverlappedResult(file, overlapped, &mut transferred, FALSE) == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(transferred)
+}
+
+/// Token of a handle registered with [`Reactor::register`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReactorToken(usize);
+
+enum Operation {
+    Pending(Option<Waker>),
+    Done(io::Result<u32>),
+}
+
+// Operations on one `OVERLAPPED` structure. The structure can be reused once
+// an operation completed, also when its future was dropped before.
+#[derive(Default)]
+struct Slot {
+    // Completions of operations whose future was dropped. They are dequeued
+    // before the completion of the current operation, which was started
+    // after they completed.
+    abandoned: usize,
+    current: Option<Operation>,
+}
+
+impl Slot {
+    fn is_unused(&self) -> bool {
+        self.abandoned == 0 && self.current.is_none()
+    }
+}
+
+// Operations by completion key and `OVERLAPPED` address, shared with the
+// completion thread.
+type Operations = Mutex<HashMap<(usize, usize), Slot>>;
+
+/// I/O completion port with a dedicated thread waiting for completions.
+///
+/// Handles opened for overlapped I/O are associated with the port with
+/// [`Reactor::register`]. Completions of their operations are awaited with
+/// [`Reactor::await_completion`]. Unlike [`await_event()`], no thread pool
+/// wait is needed per operation, a single thread serves all handles.
+///
+/// The completion thread wakes the task awaiting the operation, which posts
+/// a wake message to the thread of that task. The reactor is neither `Send`
+/// nor `Sync`, it serves the tasks of the thread that created it. Dropping
+/// the reactor stops the completion thread.
+pub struct Reactor {
+    port: HANDLE,
+    operations: Arc<Operations>,
+    next_token: Cell<usize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reactor {
+    /// Creates the completion port and starts the completion thread.
+    pub fn new() -> io::Result<Self> {
+        let port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, ptr::null_mut(), 0, 1) };
+        if port.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let operations = Arc::<Operations>::default();
+        let thread = thread::Builder::new()
+            .name("winmsg-executor-iocp".to_string())
+            .spawn({
+                let operations = operations.clone();
+                // Raw handles are not `Send`, pass it as integer.
+                let port = port as usize;
+                move || completion_thread(port as HANDLE, &operations)
+            });
+        match thread {
+            Ok(thread) => Ok(Self {
+                port,
+                operations,
+                next_token: Cell::new(0),
+                thread: Some(thread),
+            }),
+            Err(e) => {
+                unsafe { CloseHandle(port) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Associates a handle opened with `FILE_FLAG_OVERLAPPED` with the port.
+    ///
+    /// Every overlapped operation on the handle must be awaited with
+    /// [`Reactor::await_completion`] afterwards. A handle cannot be
+    /// unregistered, it stays associated until it is closed.
+    // Handles are not dereferenced, `CreateIoCompletionPort()` fails for invalid ones.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn register(&self, handle: HANDLE) -> io::Result<ReactorToken> {
+        let token = self.next_token.replace(self.next_token.get() + 1);
+        if unsafe { CreateIoCompletionPort(handle, self.port, token, 0) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ReactorToken(token))
+    }
+
+    /// Waits for an overlapped operation on the handle of `token` to complete
+    /// and returns the number of bytes transferred.
+    ///
+    /// Call it after starting the operation, also when it completed
+    /// synchronously: Its completion is queued to the port anyway, unless
+    /// `FILE_SKIP_COMPLETION_PORT_ON_SUCCESS` was set for the handle. Do not
+    /// call it when starting the operation failed.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`await_event()`], the `OVERLAPPED` structure and the
+    /// buffer must stay alive until the operation completed.
+    pub unsafe fn await_completion<'a>(
+        &'a self,
+        token: ReactorToken,
+        overlapped: &'a mut OVERLAPPED,
+    ) -> impl Future<Output = io::Result<u32>> + 'a {
+        let key = (token.0, ptr::from_mut(overlapped) as usize);
+        // The operation might have completed already. The `OVERLAPPED`
+        // structure is borrowed by one future at a time, no other operation
+        // is awaited with it.
+        self.operations
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .current
+            .get_or_insert(Operation::Pending(None));
+        Completion {
+            operations: &self.operations,
+            key,
+            done: false,
+            _overlapped: PhantomData,
+        }
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        // A completion without `OVERLAPPED` stops the completion thread.
+        unsafe { PostQueuedCompletionStatus(self.port, 0, 0, ptr::null()) };
+        let _ = self.thread.take().unwrap().join();
+        unsafe { CloseHandle(self.port) };
+    }
+}
+
+fn completion_thread(port: HANDLE, operations: &Operations) {
+    loop {
+        let mut transferred = 0;
+        let mut key = 0;
+        let mut overlapped = ptr::null_mut();
+        let success = unsafe {
+            GetQueuedCompletionStatus(port, &mut transferred, &mut key, &mut overlapped, INFINITE)
+        };
+        if overlapped.is_null() {
+            // Stopped by `Drop` or the port is gone.
+            return;
+        }
+        let result = if success != 0 {
+            Ok(transferred)
+        } else {
+            Err(io::Error::last_os_error())
+        };
+
+        let key = (key, overlapped as usize);
+        let waker = {
+            let mut operations = operations.lock().unwrap();
+            let slot = operations.entry(key).or_default();
+            let waker = if slot.abandoned > 0 {
+                // Nobody awaits this completion, the structure may be in use
+                // by another operation already.
+                slot.abandoned -= 1;
+                None
+            } else {
+                match slot.current.replace(Operation::Done(result)) {
+                    Some(Operation::Pending(waker)) => waker,
+                    // Completed before `await_completion()` was called.
+                    _ => None,
+                }
+            };
+            if slot.is_unused() {
+                operations.remove(&key);
+            }
+            waker
+        };
+        // Posts a wake message to the thread of the waiting task.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+struct Completion<'a> {
+    operations: &'a Operations,
+    key: (usize, usize),
+    done: bool,
+    _overlapped: PhantomData<&'a mut OVERLAPPED>,
+}
+
+impl Future for Completion<'_> {
+    type Output = io::Result<u32>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut operations = self.operations.lock().unwrap();
+        // The slot stays in use while the future exists.
+        let slot = operations.get_mut(&self.key).unwrap();
+        match &mut slot.current {
+            Some(Operation::Pending(waker)) => {
+                match waker {
+                    Some(waker) if waker.will_wake(cx.waker()) => {}
+                    waker => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            current @ Some(Operation::Done(_)) => {
+                let Some(Operation::Done(result)) = current.take() else {
+                    unreachable!()
+                };
+                if slot.is_unused() {
+                    operations.remove(&self.key);
+                }
+                drop(operations);
+                self.done = true;
+                Poll::Ready(result)
+            }
+            None => unreachable!("set while the future exists"),
+        }
+    }
+}
+
+impl Drop for Completion<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut operations = self.operations.lock().unwrap();
+        let slot = operations.get_mut(&self.key).unwrap();
+        // Let the completion thread skip the completion of a pending operation.
+        if let Some(Operation::Pending(_)) = slot.current.take() {
+            slot.abandoned += 1;
+        }
+        if slot.is_unused() {
+            operations.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, ffi::CString, mem, ptr};
+
+    use windows_sys::Win32::{Storage::FileSystem::*, System::Threading::CreateEventA};
+
+    use super::*;
+    use crate::block_on;
+
+    // Starts an overlapped operation and awaits its completion.
+    fn complete(
+        file: HANDLE,
+        offset: u32,
+        start: impl FnOnce(*mut OVERLAPPED) -> BOOL,
+    ) -> io::Result<u32> {
+        let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+        overlapped.Anonymous.Anonymous.Offset = offset;
+        overlapped.hEvent = unsafe { CreateEventA(ptr::null(), TRUE, FALSE, ptr::null()) };
+        assert!(!overlapped.hEvent.is_null());
+        if start(&mut overlapped) == 0 {
+            assert_eq!(unsafe { GetLastError() }, ERROR_IO_PENDING);
+        }
+        let result = block_on(unsafe { await_event(file, &mut overlapped) });
+        unsafe { CloseHandle(overlapped.hEvent) };
+        result
+    }
+
+    // Opens a temporary file for overlapped I/O, deleted when closed.
+    fn temp_file(name: &str) -> HANDLE {
+        let path = env::temp_dir().join(name);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let file = unsafe {
+            CreateFileA(
+                path.as_ptr().cast(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                ptr::null(),
+                CREATE_ALWAYS,
+                FILE_FLAG_OVERLAPPED | FILE_FLAG_DELETE_ON_CLOSE,
+                ptr::null_mut(),
+            )
+        };
+        assert_ne!(file, INVALID_HANDLE_VALUE);
+        file
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let file = temp_file("winmsg-executor-overlapped.tmp");
+        let data = b"overlapped";
+        let written = complete(file, 0, |overlapped| unsafe {
+            WriteFile(
+                file,
+                data.as_ptr(),
+                data.len() as u32,
+                ptr::null_mut(),
+                overlapped,
+            )
+        });
+        assert_eq!(written.unwrap(), data.len() as u32);
+
+        let mut buffer = [0; 16];
+        let read = complete(file, 0, |overlapped| unsafe {
+            ReadFile(
+                file,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                ptr::null_mut(),
+                overlapped,
+            )
+        });
+        assert_eq!(&buffer[..read.unwrap() as usize], data);
+
+        // Reading past the end fails with `ERROR_HANDLE_EOF`.
+        let eof = complete(file, 64, |overlapped| unsafe {
+            ReadFile(
+                file,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                ptr::null_mut(),
+                overlapped,
+            )
+        });
+        assert_eq!(
+            eof.unwrap_err().raw_os_error(),
+            Some(ERROR_HANDLE_EOF as i32)
+        );
+
+        unsafe { CloseHandle(file) };
+    }
+
+    #[test]
+    fn reactor_file_round_trip() {
+        let file = temp_file("winmsg-executor-reactor.tmp");
+        let reactor = Reactor::new().unwrap();
+        let token = reactor.register(file).unwrap();
+
+        let data = b"reactor";
+        let mut buffer = [0; 16];
+        block_on(async {
+            let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+            unsafe {
+                WriteFile(
+                    file,
+                    data.as_ptr(),
+                    data.len() as u32,
+                    ptr::null_mut(),
+                    &mut overlapped,
+                )
+            };
+            let written = unsafe { reactor.await_completion(token, &mut overlapped) }.await;
+            assert_eq!(written.unwrap(), data.len() as u32);
+
+            let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+            unsafe {
+                ReadFile(
+                    file,
+                    buffer.as_mut_ptr(),
+                    buffer.len() as u32,
+                    ptr::null_mut(),
+                    &mut overlapped,
+                )
+            };
+            let read = unsafe { reactor.await_completion(token, &mut overlapped) }.await;
+            assert_eq!(&buffer[..read.unwrap() as usize], data);
+        });
+        assert!(reactor.operations.lock().unwrap().is_empty());
+
+        unsafe { CloseHandle(file) };
+    }
+
+    #[test]
+    fn reactor_reuse_after_drop() {
+        let reactor = Reactor::new().unwrap();
+        let token = ReactorToken(0);
+        let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+
+        // Dropped before the operation completed.
+        drop(unsafe { reactor.await_completion(token, &mut overlapped) });
+
+        // The completion of the first operation is dequeued before the one
+        // of the second operation, which reuses the structure.
+        for transferred in [1, 2] {
+            let queued = unsafe {
+                PostQueuedCompletionStatus(reactor.port, transferred, token.0, &overlapped)
+            };
+            assert_ne!(queued, 0);
+        }
+        let second = unsafe { reactor.await_completion(token, &mut overlapped) };
+        assert_eq!(block_on(second).unwrap(), 2);
+        assert!(reactor.operations.lock().unwrap().is_empty());
+    }
+}
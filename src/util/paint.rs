@@ -0,0 +1,227 @@
+use std::{
+    cell::RefCell,
+    future::poll_fn,
+    mem,
+    pin::Pin,
+    ptr,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    Foundation::*,
+    Graphics::Gdi::{
+        BeginPaint, EndPaint, GetUpdateRect, InvalidateRect, UnionRect, ValidateRect, HDC,
+        PAINTSTRUCT,
+    },
+    UI::WindowsAndMessaging::WM_PAINT,
+};
+
+use super::{message_stream::Subscription, Window};
+
+struct State {
+    // Union of the update regions received since the last request was
+    // yielded, `None` when no `WM_PAINT` is pending.
+    pending: Option<RECT>,
+    waker: Option<Waker>,
+    window_alive: bool,
+}
+
+// Owned by the subscription: Dropped when the window is destroyed.
+struct Sender(Rc<RefCell<State>>);
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.0.borrow_mut();
+            state.window_alive = false;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Stream of repaint requests of a window, created with
+/// [`Window::paint_requests()`].
+///
+/// Multiple `WM_PAINT` messages received while the stream is not polled are
+/// merged into one request. Ends when the window is destroyed.
+pub struct PaintRequests {
+    hwnd: HWND,
+    state: Rc<RefCell<State>>,
+    _subscription: Option<Subscription>,
+}
+
+impl PaintRequests {
+    /// Receives the next repaint request, `None` when the window was
+    /// destroyed.
+    pub async fn recv(&mut self) -> Option<PaintRequest> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for PaintRequests {
+    type Item = PaintRequest;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PaintRequest>> {
+        let mut state = self.state.borrow_mut();
+        if let Some(rect) = state.pending.take() {
+            Poll::Ready(Some(PaintRequest {
+                hwnd: self.hwnd,
+                rect,
+            }))
+        } else if !state.window_alive {
+            Poll::Ready(None)
+        } else {
+            match &mut state.waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                waker => *waker = Some(cx.waker().clone()),
+            }
+            Poll::Pending
+        }
+    }
+}
+
+/// Request to repaint a window, yielded by [`PaintRequests`].
+pub struct PaintRequest {
+    hwnd: HWND,
+    rect: RECT,
+}
+
+impl PaintRequest {
+    /// Returns the area to repaint in client coordinates.
+    pub fn rect(&self) -> RECT {
+        self.rect
+    }
+
+    /// Starts painting with `BeginPaint()`.
+    ///
+    /// Includes areas invalidated after the request was yielded. Call it
+    /// before the next `.await` point to avoid painting a destroyed window.
+    pub fn begin(self) -> PaintGuard {
+        // `BeginPaint()` clips to the update region, which was validated
+        // when the request was received.
+        unsafe { InvalidateRect(self.hwnd, &self.rect, FALSE) };
+        let mut paint: PAINTSTRUCT = unsafe { mem::zeroed() };
+        unsafe { BeginPaint(self.hwnd, &mut paint) };
+        PaintGuard {
+            hwnd: self.hwnd,
+            paint,
+        }
+    }
+}
+
+/// Device context to paint a window, ends painting with `EndPaint()` when
+/// dropped.
+pub struct PaintGuard {
+    hwnd: HWND,
+    paint: PAINTSTRUCT,
+}
+
+impl PaintGuard {
+    /// Returns the device context to paint with, clipped to the update region.
+    pub fn hdc(&self) -> HDC {
+        self.paint.hdc
+    }
+
+    /// Returns the area to paint in client coordinates.
+    pub fn rect(&self) -> RECT {
+        self.paint.rcPaint
+    }
+}
+
+impl Drop for PaintGuard {
+    fn drop(&mut self) {
+        unsafe { EndPaint(self.hwnd, &self.paint) };
+    }
+}
+
+impl<S> Window<S> {
+    /// Marks the whole client area as to be repainted with `InvalidateRect()`.
+    ///
+    /// Windows sends `WM_PAINT` once no other messages are pending.
+    pub fn invalidate(&self) {
+        unsafe { InvalidateRect(self.hwnd(), ptr::null(), FALSE) };
+    }
+
+    /// Returns a stream of repaint requests to paint the window from a task.
+    ///
+    /// Windows sends `WM_PAINT` for as long as the update region is not
+    /// validated, so the stream validates it as soon as the message is
+    /// received: The `wndproc` closure and the default window procedure see
+    /// an empty update region. Paint the area of a [`PaintRequest`] with the
+    /// [`PaintGuard`] returned by [`PaintRequest::begin()`].
+    ///
+    /// Returns a stream that ends right away for windows of another thread.
+    pub fn paint_requests(&self) -> PaintRequests {
+        let state = Rc::new(RefCell::new(State {
+            pending: None,
+            waker: None,
+            window_alive: true,
+        }));
+        let sender = Sender(state.clone());
+        let subscription = Subscription::new(self.hwnd(), move |msg| {
+            if msg.msg != WM_PAINT {
+                return;
+            }
+            let mut rect: RECT = unsafe { mem::zeroed() };
+            unsafe {
+                GetUpdateRect(msg.hwnd, &mut rect, FALSE);
+                ValidateRect(msg.hwnd, ptr::null());
+            }
+            let waker = {
+                let mut state = sender.0.borrow_mut();
+                let pending = state.pending.get_or_insert(rect);
+                let previous = *pending;
+                unsafe { UnionRect(pending, &previous, &rect) };
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+        if subscription.is_none() {
+            state.borrow_mut().window_alive = false;
+        }
+        PaintRequests {
+            hwnd: self.hwnd(),
+            state,
+            _subscription: subscription,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{poll_ready, util::WindowType};
+
+    #[test]
+    fn paint_from_task() {
+        let w = Window::new(WindowType::TopLevel, (), |_, _| None).unwrap();
+        let mut requests = w.paint_requests();
+        assert!(poll_ready(requests.recv()).is_err());
+
+        unsafe {
+            w.send(WM_PAINT, 0, 0);
+            w.send(WM_PAINT, 0, 0);
+        }
+        let request = poll_ready(requests.recv()).unwrap().unwrap();
+        // Both messages are merged into one request.
+        assert!(poll_ready(requests.recv()).is_err());
+
+        let guard = request.begin();
+        assert!(!guard.hdc().is_null());
+        drop(guard);
+        assert_eq!(
+            unsafe { GetUpdateRect(w.hwnd(), ptr::null_mut(), FALSE) },
+            0
+        );
+
+        drop(w);
+        assert!(poll_ready(requests.recv()).unwrap().is_none());
+    }
+}
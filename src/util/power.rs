@@ -0,0 +1,134 @@
+//! Suspend and resume notifications, see [`on_suspend()`].
+
+use std::{
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    System::Power::{
+        RegisterSuspendResumeNotification, UnregisterSuspendResumeNotification, HPOWERNOTIFY,
+    },
+    UI::WindowsAndMessaging::*,
+};
+
+use super::{MessageStream, Window, WindowType};
+
+/// Power event received by a [`PowerStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    /// `PBT_APMSUSPEND`: The system is about to suspend. There are about two
+    /// seconds left to save state.
+    Suspend,
+
+    /// `PBT_APMRESUMEAUTOMATIC`: The system resumed, sent for every resume.
+    ResumeAutomatic,
+
+    /// `PBT_APMRESUMESUSPEND`: The system resumed because of user activity,
+    /// follows [`PowerEvent::ResumeAutomatic`].
+    ResumeSuspend,
+}
+
+impl PowerEvent {
+    fn from_wparam(wparam: usize) -> Option<Self> {
+        match wparam as u32 {
+            PBT_APMSUSPEND => Some(Self::Suspend),
+            PBT_APMRESUMEAUTOMATIC => Some(Self::ResumeAutomatic),
+            PBT_APMRESUMESUSPEND => Some(Self::ResumeSuspend),
+            _ => None,
+        }
+    }
+}
+
+/// Stream of power events created with [`on_suspend()`].
+///
+/// Unregisters the notification when dropped.
+pub struct PowerStream {
+    stream: MessageStream,
+    // 0 when `RegisterSuspendResumeNotification()` is not available.
+    notify: HPOWERNOTIFY,
+    _window: Window<()>,
+}
+
+/// Returns a stream of suspend and resume events.
+///
+/// The `WM_POWERBROADCAST` messages are received by a hidden
+/// [`WindowType::TopLevel`] window on the current thread. On Windows 8 and
+/// later the window is also registered with
+/// `RegisterSuspendResumeNotification()`, which delivers the events on
+/// systems with modern standby, too. The system does not wait for the
+/// suspend event to be handled, so keep the message loop responsive.
+pub fn on_suspend() -> io::Result<PowerStream> {
+    let window = Window::new(WindowType::TopLevel, (), |_, _| None)
+        .map_err(|_| io::Error::other("cannot create power notification window"))?;
+    let stream = window.messages(|msg| msg == WM_POWERBROADCAST);
+    // Optional, older versions of Windows still broadcast to top-level windows.
+    let notify =
+        unsafe { RegisterSuspendResumeNotification(window.hwnd(), DEVICE_NOTIFY_WINDOW_HANDLE) };
+    Ok(PowerStream {
+        stream,
+        notify,
+        _window: window,
+    })
+}
+
+impl PowerStream {
+    /// Receives the next event, `None` when the window was destroyed.
+    pub async fn recv(&mut self) -> Option<PowerEvent> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Drop for PowerStream {
+    fn drop(&mut self) {
+        if self.notify != 0 {
+            unsafe { UnregisterSuspendResumeNotification(self.notify) };
+        }
+    }
+}
+
+impl Stream for PowerStream {
+    type Item = PowerEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PowerEvent>> {
+        loop {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                // Skip other events, e.g. power status changes.
+                Poll::Ready(Some(msg)) => {
+                    if let Some(event) = PowerEvent::from_wparam(msg.wparam) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::poll_ready;
+
+    #[test]
+    fn suspend_resume() {
+        let mut power = on_suspend().unwrap();
+        for event in [
+            PBT_APMSUSPEND,
+            PBT_APMPOWERSTATUSCHANGE,
+            PBT_APMRESUMEAUTOMATIC,
+        ] {
+            unsafe { power._window.send(WM_POWERBROADCAST, event as usize, 0) };
+        }
+        assert_eq!(poll_ready(power.recv()).unwrap(), Some(PowerEvent::Suspend));
+        assert_eq!(
+            poll_ready(power.recv()).unwrap(),
+            Some(PowerEvent::ResumeAutomatic)
+        );
+        assert!(poll_ready(power.recv()).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! Raw input from keyboards, mice and other HID devices, see [`register()`].
+
+use std::{
+    io, mem,
+    pin::Pin,
+    ptr,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::{
+    Foundation::*,
+    UI::{Input::*, WindowsAndMessaging::WM_INPUT},
+};
+
+use super::{
+    channel, message_stream::Subscription, ChannelReceiver, Window, WindowType,
+    MESSAGE_STREAM_CAPACITY,
+};
+
+/// Mouse input, see [`RAWMOUSE`] for the meaning of the fields.
+///
+/// [`RAWMOUSE`]: https://learn.microsoft.com/en-us/windows/win32/api/winuser/ns-winuser-rawmouse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawMouse {
+    pub device: HANDLE,
+    /// `MOUSE_MOVE_*` flags, relative movement unless `MOUSE_MOVE_ABSOLUTE`.
+    pub flags: u16,
+    /// `RI_MOUSE_*` button transition flags.
+    pub button_flags: u16,
+    /// Wheel delta when `button_flags` contains `RI_MOUSE_WHEEL`.
+    pub button_data: i16,
+    pub dx: i32,
+    pub dy: i32,
+}
+
+/// Keyboard input, see [`RAWKEYBOARD`] for the meaning of the fields.
+///
+/// [`RAWKEYBOARD`]: https://learn.microsoft.com/en-us/windows/win32/api/winuser/ns-winuser-rawkeyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawKeyboard {
+    pub device: HANDLE,
+    pub make_code: u16,
+    /// `RI_KEY_*` flags.
+    pub flags: u16,
+    /// Virtual key code.
+    pub vkey: u16,
+    /// Corresponding window message, e.g. `WM_KEYDOWN`.
+    pub message: u32,
+}
+
+/// Input of another HID device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawHid {
+    pub device: HANDLE,
+    /// Size of each report in bytes.
+    pub report_size: u32,
+    /// `report_size * count` bytes of reports.
+    pub reports: Vec<u8>,
+}
+
+/// Input received by a [`RawInputStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawInput {
+    Mouse(RawMouse),
+    Keyboard(RawKeyboard),
+    Hid(RawHid),
+}
+
+/// Stream of `WM_INPUT` messages for devices registered with [`register()`].
+///
+/// Up to [`MESSAGE_STREAM_CAPACITY`] inputs are buffered while the stream is
+/// not polled, further inputs are dropped. Unregisters the devices when
+/// dropped.
+pub struct RawInputStream {
+    receiver: ChannelReceiver<RawInput>,
+    _subscription: Option<Subscription>,
+    _window: Window<()>,
+    devices: Vec<RAWINPUTDEVICE>,
+}
+
+/// Registers devices for raw input, received by a hidden message-only window
+/// on the current thread.
+///
+/// `hwndTarget` of `devices` is set to that window. Add `RIDEV_INPUTSINK` to
+/// the flags to receive input while another application is in the
+/// foreground. Each device type (usage page and usage) can be registered for
+/// one window per process only, registering it again redirects the input.
+pub fn register(devices: &[RAWINPUTDEVICE]) -> io::Result<RawInputStream> {
+    let window = Window::new(WindowType::MessageOnly, (), |_, _| None)
+        .map_err(|_| io::Error::other("cannot create raw input window"))?;
+
+    // The input data must be read while `WM_INPUT` is dispatched.
+    let (sender, receiver) = channel(MESSAGE_STREAM_CAPACITY);
+    let subscription = Subscription::new(window.hwnd(), move |msg| {
+        if msg.msg != WM_INPUT {
+            return;
+        }
+        if let Some(input) = unsafe { read_input(msg.lparam as HRAWINPUT) } {
+            // Drop input while the stream is full.
+            let _ = sender.send(input);
+        }
+    });
+
+    let devices: Vec<_> = devices
+        .iter()
+        .map(|&device| RAWINPUTDEVICE {
+            hwndTarget: window.hwnd(),
+            ..device
+        })
+        .collect();
+    register_devices(&devices)?;
+    Ok(RawInputStream {
+        receiver,
+        _subscription: subscription,
+        _window: window,
+        devices,
+    })
+}
+
+fn register_devices(devices: &[RAWINPUTDEVICE]) -> io::Result<()> {
+    let registered = unsafe {
+        RegisterRawInputDevices(
+            devices.as_ptr(),
+            devices.len() as u32,
+            mem::size_of::<RAWINPUTDEVICE>() as u32,
+        )
+    };
+    if registered == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Reads the input with the two-call pattern: Query the size, then the data.
+unsafe fn read_input(handle: HRAWINPUT) -> Option<RawInput> {
+    let header_size = mem::size_of::<RAWINPUTHEADER>() as u32;
+    let mut size = 0;
+    if GetRawInputData(handle, RID_INPUT, ptr::null_mut(), &mut size, header_size) != 0 {
+        return None;
+    }
+    // `RAWINPUT` requires 8 byte alignment.
+    let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+    let read = GetRawInputData(
+        handle,
+        RID_INPUT,
+        buffer.as_mut_ptr().cast(),
+        &mut size,
+        header_size,
+    );
+    if read == u32::MAX {
+        return None;
+    }
+    parse_input(&buffer, read as usize)
+}
+
+fn parse_input(buffer: &[u64], size: usize) -> Option<RawInput> {
+    let header_size = mem::size_of::<RAWINPUTHEADER>();
+    if size < header_size || size > buffer.len() * 8 {
+        return None;
+    }
+    let raw = buffer.as_ptr() as *const RAWINPUT;
+    // SAFETY: The header fits into the buffer, checked above. The data is read
+    // only when the size reported by the header is large enough.
+    let header = unsafe { (*raw).header };
+    let size = size.min(header.dwSize as usize);
+    let device = header.hDevice;
+    match header.dwType {
+        RIM_TYPEMOUSE if size >= header_size + mem::size_of::<RAWMOUSE>() => {
+            let mouse = unsafe { (*raw).data.mouse };
+            let buttons = unsafe { mouse.Anonymous.Anonymous };
+            Some(RawInput::Mouse(RawMouse {
+                device,
+                flags: mouse.usFlags,
+                button_flags: buttons.usButtonFlags,
+                button_data: buttons.usButtonData as i16,
+                dx: mouse.lLastX,
+                dy: mouse.lLastY,
+            }))
+        }
+        RIM_TYPEKEYBOARD if size >= header_size + mem::size_of::<RAWKEYBOARD>() => {
+            let keyboard = unsafe { (*raw).data.keyboard };
+            Some(RawInput::Keyboard(RawKeyboard {
+                device,
+                make_code: keyboard.MakeCode,
+                flags: keyboard.Flags,
+                vkey: keyboard.VKey,
+                message: keyboard.Message,
+            }))
+        }
+        RIM_TYPEHID => {
+            let data_offset = header_size + mem::offset_of!(RAWHID, bRawData);
+            if size < data_offset {
+                return None;
+            }
+            let hid = unsafe { ptr::addr_of!((*raw).data.hid).read_unaligned() };
+            let len = (hid.dwSizeHid as usize).checked_mul(hid.dwCount as usize)?;
+            let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), size) };
+            let reports = bytes.get(data_offset..data_offset.checked_add(len)?)?;
+            Some(RawInput::Hid(RawHid {
+                device,
+                report_size: hid.dwSizeHid,
+                reports: reports.to_vec(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+impl RawInputStream {
+    /// Receives the next input, `None` when the window was destroyed.
+    pub async fn recv(&mut self) -> Option<RawInput> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for RawInputStream {
+    fn drop(&mut self) {
+        let devices: Vec<_> = self
+            .devices
+            .iter()
+            .map(|&device| RAWINPUTDEVICE {
+                dwFlags: RIDEV_REMOVE,
+                hwndTarget: ptr::null_mut(),
+                ..device
+            })
+            .collect();
+        let _ = register_devices(&devices);
+    }
+}
+
+impl Stream for RawInputStream {
+    type Item = RawInput;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<RawInput>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HID_USAGE_PAGE_GENERIC: u16 = 0x01;
+    const HID_USAGE_GENERIC_MOUSE: u16 = 0x02;
+
+    #[test]
+    fn register_mouse() {
+        let mouse = RAWINPUTDEVICE {
+            usUsagePage: HID_USAGE_PAGE_GENERIC,
+            usUsage: HID_USAGE_GENERIC_MOUSE,
+            dwFlags: RIDEV_INPUTSINK,
+            hwndTarget: ptr::null_mut(),
+        };
+        let stream = register(&[mouse]).unwrap();
+        drop(stream);
+    }
+
+    #[test]
+    fn parse_mouse() {
+        let mut buffer = vec![0u64; mem::size_of::<RAWINPUT>().div_ceil(8)];
+        let raw = buffer.as_mut_ptr() as *mut RAWINPUT;
+        unsafe {
+            (*raw).header.dwType = RIM_TYPEMOUSE;
+            (*raw).header.dwSize = mem::size_of::<RAWINPUT>() as u32;
+            (*raw).data.mouse.lLastX = -3;
+            (*raw).data.mouse.lLastY = 5;
+            (*raw).data.mouse.Anonymous.Anonymous.usButtonData = (-120i16) as u16;
+        }
+        let Some(RawInput::Mouse(mouse)) = parse_input(&buffer, mem::size_of::<RAWINPUT>()) else {
+            panic!("not parsed as mouse input");
+        };
+        assert_eq!((mouse.dx, mouse.dy, mouse.button_data), (-3, 5, -120));
+
+        // Truncated input.
+        assert!(parse_input(&buffer, 4).is_none());
+    }
+}
@@ -0,0 +1,215 @@
+//! Socket readiness with `WSAAsyncSelect()`, see [`AsyncSocket`].
+
+use std::{
+    cell::Cell,
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use windows_sys::Win32::{
+    Networking::WinSock::*,
+    UI::WindowsAndMessaging::{WM_NULL, WM_USER},
+};
+
+use super::{Window, WindowType};
+
+// Posted to the window of a socket for each network event.
+const WM_SOCKET: u32 = WM_USER;
+
+const READ_EVENTS: u32 = FD_READ | FD_ACCEPT | FD_CLOSE;
+const WRITE_EVENTS: u32 = FD_WRITE | FD_CONNECT | FD_CLOSE;
+
+#[derive(Default)]
+struct State {
+    // `FD_*` bits of the events received and not yet awaited. `FD_CLOSE`
+    // stays set, the socket cannot become ready again after it.
+    events: Cell<u32>,
+    // Error code of the last event that reported one.
+    error: Cell<i32>,
+    read_waker: Cell<Option<Waker>>,
+    write_waker: Cell<Option<Waker>>,
+}
+
+impl State {
+    fn poll_ready(
+        &self,
+        cx: &mut Context<'_>,
+        mask: u32,
+        waker: &Cell<Option<Waker>>,
+    ) -> Poll<io::Result<()>> {
+        let events = self.events.get();
+        if events & mask == 0 {
+            let mut stored = waker.take();
+            match &mut stored {
+                Some(w) if w.will_wake(cx.waker()) => {}
+                stored => *stored = Some(cx.waker().clone()),
+            }
+            waker.set(stored);
+            return Poll::Pending;
+        }
+        self.events.set(events & !(mask & !FD_CLOSE));
+        match self.error.replace(0) {
+            0 => Poll::Ready(Ok(())),
+            error => Poll::Ready(Err(io::Error::from_raw_os_error(error))),
+        }
+    }
+}
+
+/// Socket registered with `WSAAsyncSelect()` for readiness notifications.
+///
+/// A hidden [`WindowType::MessageOnly`] window on the current thread receives
+/// a message for each `FD_READ`, `FD_WRITE`, `FD_ACCEPT`, `FD_CONNECT` and
+/// `FD_CLOSE` network event, which wakes the task waiting in
+/// [`AsyncSocket::readable`] or [`AsyncSocket::writable`]. Use the regular
+/// Winsock functions for the I/O itself.
+///
+/// **`WSAAsyncSelect()` puts the socket in non-blocking mode.** Calls that
+/// would block fail with `WSAEWOULDBLOCK` instead, await readiness then and
+/// retry. The socket stays non-blocking when the registration is dropped;
+/// switch it back with `ioctlsocket(FIONBIO)` when needed.
+///
+/// The socket is not closed when dropped, only the registration is removed.
+pub struct AsyncSocket {
+    socket: SOCKET,
+    window: Window<State>,
+}
+
+impl AsyncSocket {
+    /// Registers `socket` for readiness notifications.
+    pub fn new(socket: SOCKET) -> io::Result<Self> {
+        let window = Window::new(WindowType::MessageOnly, State::default(), |state, msg| {
+            if msg.msg != WM_SOCKET {
+                return None;
+            }
+            let event = msg.lparam as u32 & 0xFFFF;
+            let error = (msg.lparam as u32 >> 16) as i32;
+            state.events.set(state.events.get() | event);
+            if error != 0 {
+                state.error.set(error);
+            }
+            for (mask, waker) in [
+                (READ_EVENTS, &state.read_waker),
+                (WRITE_EVENTS, &state.write_waker),
+            ] {
+                if event & mask != 0 {
+                    if let Some(waker) = waker.take() {
+                        waker.wake();
+                    }
+                }
+            }
+            Some(0)
+        })
+        .map_err(|_| io::Error::other("cannot create socket notification window"))?;
+
+        let events = READ_EVENTS | WRITE_EVENTS;
+        if unsafe { WSAAsyncSelect(socket, window.hwnd(), WM_SOCKET, events as i32) } != 0 {
+            return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+        }
+        Ok(Self { socket, window })
+    }
+
+    /// Returns the registered socket.
+    pub fn socket(&self) -> SOCKET {
+        self.socket
+    }
+
+    /// Waits for an `FD_READ`, `FD_ACCEPT` or `FD_CLOSE` event.
+    ///
+    /// The event is consumed, await it again when `recv()` or `accept()`
+    /// fails with `WSAEWOULDBLOCK`. Winsock posts a new `FD_READ` after each
+    /// `recv()` that leaves data behind, so a partial read does not wait.
+    /// Resolves right away once the socket was closed. Returns the error code
+    /// of the event, e.g. when the connection was reset.
+    pub async fn readable(&self) -> io::Result<()> {
+        let state = self.state();
+        poll_fn(|cx| state.poll_ready(cx, READ_EVENTS, &state.read_waker)).await
+    }
+
+    /// Waits for an `FD_WRITE`, `FD_CONNECT` or `FD_CLOSE` event.
+    ///
+    /// The event is consumed. Winsock posts `FD_WRITE` only once a connection
+    /// is established and after `send()` failed with `WSAEWOULDBLOCK`, so
+    /// only await it before sending on a new connection or after such a
+    /// failure. Returns the error code of the event, e.g. when `connect()`
+    /// failed.
+    pub async fn writable(&self) -> io::Result<()> {
+        let state = self.state();
+        poll_fn(|cx| state.poll_ready(cx, WRITE_EVENTS, &state.write_waker)).await
+    }
+
+    fn state(&self) -> Pin<&State> {
+        self.window.state()
+    }
+}
+
+impl Drop for AsyncSocket {
+    fn drop(&mut self) {
+        unsafe { WSAAsyncSelect(self.socket, self.window.hwnd(), WM_NULL, 0) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        mem,
+        net::{Ipv4Addr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn connect_and_receive() {
+        // Also initializes Winsock for the raw socket.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        let client = unsafe { socket(AF_INET as i32, SOCK_STREAM, IPPROTO_TCP) };
+        assert_ne!(client, INVALID_SOCKET);
+        let socket = AsyncSocket::new(client).unwrap();
+
+        let mut addr: SOCKADDR_IN = unsafe { mem::zeroed() };
+        addr.sin_family = AF_INET;
+        addr.sin_port = port.to_be();
+        addr.sin_addr.S_un.S_addr = u32::from(Ipv4Addr::LOCALHOST).to_be();
+        let result = unsafe {
+            connect(
+                client,
+                (&addr as *const SOCKADDR_IN).cast(),
+                mem::size_of::<SOCKADDR_IN>() as i32,
+            )
+        };
+        assert_eq!(result, SOCKET_ERROR);
+        assert_eq!(unsafe { WSAGetLastError() }, WSAEWOULDBLOCK);
+
+        let received = block_on(async {
+            socket.writable().await.unwrap();
+            let mut received = Vec::new();
+            loop {
+                let mut buf = [0; 16];
+                let len = unsafe { recv(client, buf.as_mut_ptr(), buf.len() as i32, 0) };
+                match len {
+                    0 => return received,
+                    SOCKET_ERROR => {
+                        assert_eq!(unsafe { WSAGetLastError() }, WSAEWOULDBLOCK);
+                        socket.readable().await.unwrap();
+                    }
+                    len => received.extend_from_slice(&buf[..len as usize]),
+                }
+            }
+        });
+        assert_eq!(received, b"hello");
+
+        server.join().unwrap();
+        drop(socket);
+        unsafe { closesocket(client) };
+    }
+}
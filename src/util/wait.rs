@@ -0,0 +1,175 @@
+use std::{
+    ffi::c_void,
+    future::poll_fn,
+    io, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use windows_sys::Win32::{
+    Foundation::*,
+    System::Threading::{
+        GetExitCodeProcess, RegisterWaitForSingleObject, UnregisterWaitEx, INFINITE,
+        WT_EXECUTEONLYONCE,
+    },
+};
+
+// Shared with the thread pool callback.
+struct WaitState {
+    signaled: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Thread pool wait registration, unregistered when dropped.
+struct Wait {
+    wait_handle: HANDLE,
+    // Boxed so that the pointer passed to the callback stays valid when the
+    // registration is moved.
+    state: Box<WaitState>,
+}
+
+impl Wait {
+    fn register(object: HANDLE) -> io::Result<Self> {
+        let state = Box::new(WaitState {
+            signaled: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        });
+        let mut wait_handle = ptr::null_mut();
+        let success = unsafe {
+            RegisterWaitForSingleObject(
+                &mut wait_handle,
+                object,
+                Some(wait_callback),
+                ptr::from_ref(&*state).cast(),
+                INFINITE,
+                WT_EXECUTEONLYONCE,
+            )
+        };
+        if success == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { wait_handle, state })
+    }
+
+    fn poll_signaled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Store the waker before checking the flag so that a signal arriving
+        // in between is not missed.
+        *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        if self.state.signaled.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        // Blocks until a running callback has returned, only then it is safe
+        // to deallocate the state.
+        unsafe { UnregisterWaitEx(self.wait_handle, INVALID_HANDLE_VALUE) };
+    }
+}
+
+// Runs on a thread pool thread.
+unsafe extern "system" fn wait_callback(context: *mut c_void, _timed_out: BOOLEAN) {
+    let state = &*(context as *const WaitState);
+    state.signaled.store(true, Ordering::SeqCst);
+    if let Some(waker) = state.waker.lock().unwrap().take() {
+        // Posts a wake message to the thread of the waiting task.
+        waker.wake();
+    }
+}
+
+/// Waits for a kernel object (event, process, mutex, ...) to be signaled.
+///
+/// The wait is performed by the system thread pool with
+/// [`RegisterWaitForSingleObject()`], which wakes the task when the object is
+/// signaled. The message loop keeps running in the meantime. Dropping the
+/// future cancels the wait.
+///
+/// The handle must stay valid until the future completes or is dropped.
+///
+/// [`RegisterWaitForSingleObject()`]: https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-registerwaitforsingleobject
+pub async fn wait_for_handle(handle: HANDLE) -> io::Result<()> {
+    let wait = Wait::register(handle)?;
+    poll_fn(|cx| wait.poll_signaled(cx)).await;
+    Ok(())
+}
+
+/// Waits for a process to exit and returns its exit code.
+///
+/// The process handle requires the `SYNCHRONIZE` access right for waiting
+/// and `PROCESS_QUERY_LIMITED_INFORMATION` to query the exit code. Handles
+/// returned by `CreateProcess()` have full access.
+///
+/// Returns an error when the exit code is `STILL_ACTIVE` (259): The value is
+/// indistinguishable from a running process and should not be used as exit
+/// code.
+pub async fn wait_for_process(handle: HANDLE) -> io::Result<u32> {
+    wait_for_handle(handle).await?;
+    let mut exit_code = 0;
+    if unsafe { GetExitCodeProcess(handle, &mut exit_code) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if exit_code == STILL_ACTIVE as u32 {
+        return Err(io::Error::other("process exit code is STILL_ACTIVE"));
+    }
+    Ok(exit_code)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{process::Command, thread, time::Duration};
+
+    use windows_sys::Win32::System::Threading::{
+        CreateEventA, OpenProcess, SetEvent, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE,
+    };
+
+    use super::*;
+    use crate::block_on;
+
+    #[test]
+    fn wait_for_event() {
+        let event = unsafe { CreateEventA(ptr::null(), 1, 0, ptr::null()) };
+        assert!(!event.is_null());
+
+        // Raw handles are not `Send`, pass it as integer.
+        let event_addr = event as usize;
+        let signal_thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            unsafe { SetEvent(event_addr as HANDLE) };
+        });
+
+        block_on(wait_for_handle(event)).unwrap();
+
+        signal_thread.join().unwrap();
+        unsafe { CloseHandle(event) };
+    }
+
+    #[test]
+    fn wait_for_invalid_handle() {
+        assert!(block_on(wait_for_handle(ptr::null_mut())).is_err());
+    }
+
+    #[test]
+    fn wait_for_process_exit_code() {
+        let mut child = Command::new("cmd").args(["/c", "exit 3"]).spawn().unwrap();
+        let process = unsafe {
+            OpenProcess(
+                PROCESS_SYNCHRONIZE | PROCESS_QUERY_LIMITED_INFORMATION,
+                0,
+                child.id(),
+            )
+        };
+        assert!(!process.is_null());
+
+        let exit_code = block_on(wait_for_process(process)).unwrap();
+        assert_eq!(exit_code, 3);
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        unsafe { CloseHandle(process) };
+    }
+}
@@ -1,13 +1,28 @@
 use std::{
-    cell::RefCell,
+    any::TypeId,
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt, io,
     marker::PhantomData,
     mem,
     pin::Pin,
     ptr::{self, NonNull},
-    sync::Once,
+    sync::Mutex,
 };
 
-use windows_sys::Win32::{Foundation::*, UI::WindowsAndMessaging::*};
+use windows_sys::{
+    core::PCWSTR,
+    w,
+    Win32::{
+        Foundation::*,
+        System::Threading::GetCurrentThreadId,
+        UI::{
+            Shell::{DefSubclassProc, RemoveWindowSubclass, SetWindowSubclass},
+            WindowsAndMessaging::*,
+        },
+    },
+};
 
 // Taken from:
 // https://github.com/rust-windowing/winit/blob/v0.30.0/src/platform_impl/windows/util.rs#L140
@@ -25,11 +40,39 @@ fn get_instance_handle() -> HINSTANCE {
     unsafe { ptr::from_ref(&__ImageBase) as _ }
 }
 
+// Converts to a null terminated UTF-16 string for the `W` APIs.
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain([0]).collect()
+}
+
 struct SubClassInformation {
     wndproc: unsafe extern "system" fn(HWND, u32, WPARAM, LPARAM) -> LRESULT,
     // Erased pointer type allows `wndproc_setup` to be free of generics.
     // It simply forwards the pointer and does not need to know type details.
     user_data: *const (),
+    state_type: TypeId,
+    user_data_slot: UserDataSlot,
+}
+
+// Name of the window property used by `UserDataSlot::Property`.
+const USER_DATA_PROPERTY: PCWSTR = w!("winmsg-executor-user-data");
+
+thread_local! {
+    // Windows created on this thread, to look up their state by handle.
+    static WINDOW_REGISTRY: RefCell<HashMap<HWND, (TypeId, *const ())>> =
+        RefCell::new(HashMap::new());
+
+    // Set when the `wndproc` closure rejects the creation of a window.
+    static CREATE_REJECTED: Cell<Option<CreateStage>> = const { Cell::new(None) };
+}
+
+fn registered_user_data<S: 'static>(hwnd: HWND) -> Option<*const UserData<S, ()>> {
+    WINDOW_REGISTRY.with_borrow(|registry| {
+        let (state_type, user_data) = registry.get(&hwnd)?;
+        // `UserData` is `repr(C)`: The state is located at the start no matter
+        // the type of the closure.
+        (*state_type == TypeId::of::<S>()).then_some(user_data.cast())
+    })
 }
 
 /// Wrapper for the arguments to the [`WNDPROC callback function`](https://learn.microsoft.com/en-us/windows/win32/api/winuser/nc-winuser-wndproc).
@@ -47,18 +90,109 @@ struct UserData<S, F> {
     wndproc: F,
 }
 
+type SubclassProc = unsafe extern "system" fn(HWND, u32, WPARAM, LPARAM, usize, usize) -> LRESULT;
+
+// Identifies our subclass among other subclasses of the same window.
+const SUBCLASS_ID: usize = 0;
+
+// Bookkeeping for a foreign window attached to with `Window::attach()`.
+#[derive(Debug)]
+struct Subclass {
+    proc: SubclassProc,
+    // Lives in the subclass reference data instead of `GWLP_USERDATA`, which
+    // belongs to the owner of the window.
+    user_data: *const (),
+    drop_user_data: unsafe fn(*const ()),
+}
+
 /// Owned window handle.
 ///
-/// Dropping the handle destroys the window.
+/// Dropping the handle destroys the window. For windows attached to with
+/// [`Window::attach()`], dropping only removes the `wndproc` closure.
 #[derive(Debug)]
 pub struct Window<S> {
     hwnd: HWND,
+    subclass: Option<Subclass>,
+    user_data: *const (),
     _state: PhantomData<S>,
 }
 
 impl<S> Drop for Window<S> {
     fn drop(&mut self) {
-        unsafe { DestroyWindow(self.hwnd) };
+        match &self.subclass {
+            None => unsafe {
+                DestroyWindow(self.hwnd);
+            },
+            Some(subclass) => unsafe {
+                // Fails when the window was destroyed already, in which case
+                // the subclass was removed in `WM_NCDESTROY`.
+                RemoveWindowSubclass(self.hwnd, Some(subclass.proc), SUBCLASS_ID);
+                (subclass.drop_user_data)(subclass.user_data);
+            },
+        }
+    }
+}
+
+/// Reference to a window on the current thread, see [`Window::from_hwnd()`].
+#[derive(Debug)]
+pub struct WindowRef<S> {
+    hwnd: HWND,
+    _state: PhantomData<*const S>,
+}
+
+impl<S: 'static> WindowRef<S> {
+    /// Returns the raw window handle.
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    /// Calls `f` with the state of the window.
+    ///
+    /// Returns `None` when the window has been destroyed in the meantime.
+    ///
+    /// # Safety
+    ///
+    /// The window must not be destroyed while `f` runs, e.g. by dropping the
+    /// owning [`Window`] or by dispatching a message that drops it. A
+    /// [`WindowRef`] does not borrow the owning [`Window`], so the state is
+    /// deallocated out from under `f` in that case.
+    pub unsafe fn with_state<R>(&self, f: impl FnOnce(Pin<&S>) -> R) -> Option<R> {
+        let user_data = registered_user_data::<S>(self.hwnd)?;
+        Some(f(unsafe { Pin::new_unchecked(&(*user_data).state) }))
+    }
+}
+
+// Raw handles are type aliases of `windows-sys`, for which the orphan rule
+// forbids conversions. Use `windows::Win32::Foundation::HWND(hwnd)` and
+// `.0` instead.
+#[cfg(feature = "windows-interop")]
+impl<S> From<&Window<S>> for windows::Win32::Foundation::HWND {
+    fn from(window: &Window<S>) -> Self {
+        Self(window.hwnd)
+    }
+}
+
+#[cfg(feature = "windows-interop")]
+impl<S> From<&WindowRef<S>> for windows::Win32::Foundation::HWND {
+    fn from(window: &WindowRef<S>) -> Self {
+        Self(window.hwnd)
+    }
+}
+
+/// Looks up the window with [`Window::from_hwnd()`].
+#[cfg(feature = "windows-interop")]
+impl<S: 'static> TryFrom<windows::Win32::Foundation::HWND> for WindowRef<S> {
+    type Error = ();
+
+    fn try_from(hwnd: windows::Win32::Foundation::HWND) -> Result<Self, Self::Error> {
+        Window::from_hwnd(hwnd.0).ok_or(())
+    }
+}
+
+#[cfg(feature = "windows-interop")]
+impl<S> From<&Window<S>> for windows::Win32::Foundation::HINSTANCE {
+    fn from(window: &Window<S>) -> Self {
+        Self(window.hinstance())
     }
 }
 
@@ -75,62 +209,218 @@ pub enum WindowType {
     MessageOnly,
 }
 
+/// Where a window created with [`WindowBuilder`] stores the pointer to its
+/// state and closure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UserDataSlot {
+    /// `GWLP_USERDATA`, the fastest option.
+    #[default]
+    WindowLongPtr,
+
+    /// A window property set with `SetPropW()`, which leaves `GWLP_USERDATA`
+    /// free for other code, e.g. when the window is subclassed by a framework
+    /// that uses it. Looking up a property is slower than `GWLP_USERDATA`.
+    Property,
+}
+
 /// Window could not be created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCreationError {
+    /// `GetLastError()` code of the failed call, 0 when the failure was not
+    /// reported by Windows.
+    pub code: u32,
+
+    /// What failed.
+    pub stage: CreateStage,
+}
+
+/// Stage of window creation at which a [`WindowCreationError`] occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateStage {
+    /// The window class could not be registered, e.g. because the class
+    /// name is registered by other code already.
+    RegisterClass,
+
+    /// `CreateWindowExW()` failed, e.g. because the maximum number of 10000
+    /// window handles per process was reached:
+    /// <https://devblogs.microsoft.com/oldnewthing/20070718-00/?p=25963>
+    CreateWindow,
+
+    /// The `WM_NCCREATE` message was handled but returned 0.
+    NcCreateRejected,
+
+    /// The `WM_CREATE` message was handled but returned -1.
+    CreateRejected,
+
+    /// [`Window::attach()`] was called for a window of another thread.
+    WrongThread,
+
+    /// Installing the window subclass failed.
+    Subclass,
+}
+
+impl WindowCreationError {
+    fn last_error(stage: CreateStage) -> Self {
+        Self {
+            code: unsafe { GetLastError() },
+            stage,
+        }
+    }
+}
+
+impl fmt::Display for WindowCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self.stage {
+            CreateStage::RegisterClass => "cannot register window class",
+            CreateStage::CreateWindow => "cannot create window",
+            CreateStage::NcCreateRejected => "window creation rejected by WM_NCCREATE",
+            CreateStage::CreateRejected => "window creation rejected by WM_CREATE",
+            CreateStage::WrongThread => "window belongs to another thread",
+            CreateStage::Subclass => "cannot subclass window",
+        };
+        if self.code == 0 {
+            f.write_str(stage)
+        } else {
+            write!(f, "{stage} (error code {})", self.code)
+        }
+    }
+}
+
+impl Error for WindowCreationError {}
+
+/// Builder to configure the window class and styles of a [`Window`].
 ///
-/// Possible failure reasons:
-/// * `WM_NCCREATE` message was handled but did not return 0
-/// * `WM_CREATE` message was handled but returned -1
-/// * Reached the maximum number of 10000 window handles per process:
-///   <https://devblogs.microsoft.com/oldnewthing/20070718-00/?p=25963>
-#[derive(Debug)]
-pub struct WindowCreationError;
+/// Defaults to the same configuration used by [`Window::new()`].
+///
+/// Windows are created as Unicode windows with the `W` APIs: Class names and
+/// titles are converted to UTF-16 and `WM_CHAR` carries UTF-16 code units.
+#[derive(Debug, Clone)]
+pub struct WindowBuilder {
+    window_type: WindowType,
+    // Null terminated UTF-16.
+    class_name: Vec<u16>,
+    title: Option<Vec<u16>>,
+    style: WINDOW_STYLE,
+    ex_style: WINDOW_EX_STYLE,
+    rect: Option<(i32, i32, i32, i32)>,
+    user_data_slot: UserDataSlot,
+}
 
-impl<S> Window<S> {
-    /// Creates a new window with a `wndproc` closure.
+impl WindowBuilder {
+    /// Creates a builder for a window of the given type.
+    pub fn new(window_type: WindowType) -> Self {
+        Self {
+            window_type,
+            class_name: to_wide("winmsg-executor"),
+            title: None,
+            style: 0,
+            ex_style: 0,
+            rect: None,
+            user_data_slot: UserDataSlot::WindowLongPtr,
+        }
+    }
+
+    /// Sets the window class name, defaults to `winmsg-executor`.
     ///
-    /// The `state` parameter will be allocated alongside the closure. It is
-    /// meant as a convenient alternative to `Rc<State>` to access to variables
-    /// from both inside and outside of the closure. A pinned reference to the
-    /// state is passed as first parameter the closure. Use [`Window::state()`]
-    /// to access the state from the outside.
-    pub fn new<F>(
-        window_type: WindowType,
-        state: S,
-        wndproc: F,
-    ) -> Result<Self, WindowCreationError>
-    where
-        F: Fn(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
-    {
-        let class_name = c"winmsg-executor".as_ptr().cast();
+    /// Each class is registered once on first use. Registration fails when a
+    /// class with the same name was registered by other code.
+    pub fn class_name(mut self, class_name: &str) -> Self {
+        self.class_name = to_wide(class_name);
+        self
+    }
 
+    /// Sets the window title, defaults to no title.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(to_wide(title));
+        self
+    }
+
+    /// Sets the `WS_*` window style, defaults to `0`.
+    pub fn style(mut self, style: WINDOW_STYLE) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Sets the `WS_EX_*` extended window style, defaults to `0`.
+    pub fn ex_style(mut self, ex_style: WINDOW_EX_STYLE) -> Self {
+        self.ex_style = ex_style;
+        self
+    }
+
+    /// Sets the position and size of the window in pixels.
+    ///
+    /// Defaults to `CW_USEDEFAULT`, which lets the system choose.
+    pub fn rect(mut self, x: i32, y: i32, width: i32, height: i32) -> Self {
+        self.rect = Some((x, y, width, height));
+        self
+    }
+
+    /// Sets where the window stores the pointer to its state, defaults to
+    /// [`UserDataSlot::WindowLongPtr`].
+    pub fn user_data_slot(mut self, user_data_slot: UserDataSlot) -> Self {
+        self.user_data_slot = user_data_slot;
+        self
+    }
+
+    fn register_class(&self) -> Result<(), WindowCreationError> {
         // A class must only be unregistered when it was registered from a DLL which
         // is unloaded during program execution: For now, an unsupported use case.
-        static CLASS_REGISTRATION: Once = Once::new();
-        CLASS_REGISTRATION.call_once(|| {
-            let mut wnd_class: WNDCLASSA = unsafe { std::mem::zeroed() };
-            wnd_class.lpfnWndProc = Some(wndproc_setup);
-            wnd_class.hInstance = get_instance_handle();
-            wnd_class.lpszClassName = class_name;
-            unsafe { RegisterClassA(&wnd_class) };
-        });
+        static REGISTERED_CLASSES: Mutex<BTreeSet<Vec<u16>>> = Mutex::new(BTreeSet::new());
+        let mut registered_classes = REGISTERED_CLASSES.lock().unwrap();
+        if registered_classes.contains(&self.class_name) {
+            return Ok(());
+        }
+
+        let mut wnd_class: WNDCLASSW = unsafe { std::mem::zeroed() };
+        wnd_class.lpfnWndProc = Some(wndproc_setup);
+        wnd_class.hInstance = get_instance_handle();
+        wnd_class.lpszClassName = self.class_name.as_ptr();
+        // Do not try to create windows of a class registered by someone else,
+        // its window procedure does not know about our user data.
+        if unsafe { RegisterClassW(&wnd_class) } == 0 {
+            return Err(WindowCreationError::last_error(CreateStage::RegisterClass));
+        }
+        registered_classes.insert(self.class_name.clone());
+        Ok(())
+    }
+
+    /// Creates the window, see [`Window::new()`].
+    pub fn build<S, F>(self, state: S, wndproc: F) -> Result<Window<S>, WindowCreationError>
+    where
+        S: 'static,
+        F: Fn(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
+    {
+        self.register_class()?;
 
         // Pass the closure and state as user data to our typed window process.
         let subclassinfo = SubClassInformation {
-            wndproc: wndproc_typed::<S, F>,
+            wndproc: match self.user_data_slot {
+                UserDataSlot::WindowLongPtr => wndproc_typed::<S, F, false>,
+                UserDataSlot::Property => wndproc_typed::<S, F, true>,
+            },
             user_data: Box::into_raw(Box::new(UserData { state, wndproc })).cast(),
+            state_type: TypeId::of::<S>(),
+            user_data_slot: self.user_data_slot,
         };
 
+        let (x, y, width, height) =
+            self.rect
+                .unwrap_or((CW_USEDEFAULT, CW_USEDEFAULT, CW_USEDEFAULT, CW_USEDEFAULT));
+
+        CREATE_REJECTED.set(None);
         let hwnd = unsafe {
-            CreateWindowExA(
-                0,
-                class_name,
-                ptr::null(),
-                0,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                CW_USEDEFAULT,
-                match window_type {
+            CreateWindowExW(
+                self.ex_style,
+                self.class_name.as_ptr(),
+                self.title
+                    .as_ref()
+                    .map_or(ptr::null(), |title| title.as_ptr()),
+                self.style,
+                x,
+                y,
+                width,
+                height,
+                match self.window_type {
                     WindowType::TopLevel => ptr::null_mut(),
                     WindowType::MessageOnly => HWND_MESSAGE,
                 },
@@ -138,20 +428,66 @@ impl<S> Window<S> {
                 get_instance_handle(),
                 // The subclass info can be passed as a pointer to the stack
                 // allocated variable because it will only be accessed during
-                // the `CreateWindowExA()` call and not afterward.
+                // the `CreateWindowExW()` call and not afterward.
                 ptr::from_ref(&subclassinfo).cast(),
             )
         };
         if hwnd.is_null() {
-            return Err(WindowCreationError);
+            let mut error = WindowCreationError::last_error(CreateStage::CreateWindow);
+            if let Some(stage) = CREATE_REJECTED.take() {
+                error.stage = stage;
+            }
+            return Err(error);
         }
 
-        Ok(Self {
+        Ok(Window {
             hwnd,
+            subclass: None,
+            user_data: subclassinfo.user_data,
             _state: PhantomData,
         })
     }
 
+    /// Creates the window, see [`Window::new_checked()`].
+    pub fn build_checked<S, F>(self, state: S, wndproc: F) -> Result<Window<S>, WindowCreationError>
+    where
+        S: 'static,
+        F: FnMut(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
+    {
+        let wndproc = RefCell::new(wndproc);
+        self.build(state, move |state, msg| {
+            // Detect when `wndproc` is re-entered, which can happen when the user
+            // provided handler creates a modal dialog (e.g., a popup-menu). Rust rules
+            // do not allow us to create a second mutable reference to the user-provided
+            // handler. Run the default windows procedure instead.
+            let mut wndproc = wndproc.try_borrow_mut().ok()?;
+            wndproc(state, msg)
+        })
+    }
+}
+
+impl<S> Window<S> {
+    /// Creates a new window with a `wndproc` closure.
+    ///
+    /// The `state` parameter will be allocated alongside the closure. It is
+    /// meant as a convenient alternative to `Rc<State>` to access to variables
+    /// from both inside and outside of the closure. A pinned reference to the
+    /// state is passed as first parameter the closure. Use [`Window::state()`]
+    /// to access the state from the outside.
+    ///
+    /// Use [`WindowBuilder`] to configure the window class and styles.
+    pub fn new<F>(
+        window_type: WindowType,
+        state: S,
+        wndproc: F,
+    ) -> Result<Self, WindowCreationError>
+    where
+        S: 'static,
+        F: Fn(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
+    {
+        WindowBuilder::new(window_type).build(state, wndproc)
+    }
+
     /// Same as [`Window::new()`] but allows the closure to be `FnMut`.
     ///
     /// Internally uses a `RefCell` for the closure to prevent it from being
@@ -163,21 +499,76 @@ impl<S> Window<S> {
         wndproc: F,
     ) -> Result<Self, WindowCreationError>
     where
+        S: 'static,
         F: FnMut(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
     {
-        let wndproc = RefCell::new(wndproc);
-        Self::new(window_type, state, move |state, msg| {
-            // Detect when `wndproc` is re-entered, which can happen when the user
-            // provided handler creates a modal dialog (e.g., a popup-menu). Rust rules
-            // do not allow us to create a second mutable reference to the user-provided
-            // handler. Run the default windows procedure instead.
-            let mut wndproc = wndproc.try_borrow_mut().ok()?;
-            wndproc(state, msg)
+        WindowBuilder::new(window_type).build_checked(state, wndproc)
+    }
+
+    /// Looks up a window created on the current thread by its handle.
+    ///
+    /// Returns `None` when the handle does not belong to a [`Window`] created
+    /// on this thread or when its state is not of type `S`. Windows attached to
+    /// with [`Window::attach()`] are not considered.
+    pub fn from_hwnd(hwnd: HWND) -> Option<WindowRef<S>>
+    where
+        S: 'static,
+    {
+        registered_user_data::<S>(hwnd)?;
+        Some(WindowRef {
+            hwnd,
+            _state: PhantomData,
+        })
+    }
+
+    /// Attaches a `wndproc` closure to an existing window, e.g. one created
+    /// by another framework.
+    ///
+    /// Installs the closure with [`SetWindowSubclass()`] without taking
+    /// ownership of the window: Dropping the returned handle restores the
+    /// previous window procedure but does not destroy the window. Messages for
+    /// which the closure returns `None` are forwarded to the previous window
+    /// procedure. `GWLP_USERDATA` is left untouched for use by the owner.
+    ///
+    /// Fails when the window belongs to another thread.
+    ///
+    /// # Safety
+    ///
+    /// `hwnd` must be a valid window handle.
+    ///
+    /// [`SetWindowSubclass()`]: https://learn.microsoft.com/en-us/windows/win32/api/commctrl/nf-commctrl-setwindowsubclass
+    pub unsafe fn attach<F>(hwnd: HWND, state: S, wndproc: F) -> Result<Self, WindowCreationError>
+    where
+        F: Fn(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
+    {
+        if GetWindowThreadProcessId(hwnd, ptr::null_mut()) != GetCurrentThreadId() {
+            return Err(WindowCreationError {
+                code: 0,
+                stage: CreateStage::WrongThread,
+            });
+        }
+
+        let user_data: *const () = Box::into_raw(Box::new(UserData { state, wndproc })).cast();
+        let subclass = Subclass {
+            proc: subclass_proc::<S, F>,
+            user_data,
+            drop_user_data: drop_user_data::<S, F>,
+        };
+        if SetWindowSubclass(hwnd, Some(subclass.proc), SUBCLASS_ID, user_data as usize) == 0 {
+            drop_user_data::<S, F>(user_data);
+            return Err(WindowCreationError::last_error(CreateStage::Subclass));
+        }
+
+        Ok(Self {
+            hwnd,
+            subclass: Some(subclass),
+            user_data,
+            _state: PhantomData,
         })
     }
 
     fn user_data(&self) -> &UserData<S, ()> {
-        unsafe { &*(GetWindowLongPtrA(self.hwnd, GWLP_USERDATA) as *const _) }
+        unsafe { &*(self.user_data as *const _) }
     }
 
     /// Returns this windows raw window handle.
@@ -185,12 +576,113 @@ impl<S> Window<S> {
         self.hwnd
     }
 
+    /// Returns the instance handle of the module that created the window.
+    ///
+    /// For windows attached to with [`Window::attach()`] this is the module
+    /// of the other framework.
+    pub fn hinstance(&self) -> HINSTANCE {
+        unsafe { GetWindowLongPtrW(self.hwnd, GWLP_HINSTANCE) as _ }
+    }
+
+    /// Posts a message to this window with `PostMessageW()`.
+    ///
+    /// Returns immediately, the message is dispatched by the message loop.
+    /// Fails when the message queue is full.
+    ///
+    /// # Safety
+    ///
+    /// `wparam` and `lparam` must be valid for `msg`. Some system messages
+    /// interpret them as pointers, e.g. `WM_TIMER` calls `lparam` as function
+    /// when forwarded to the default window procedure.
+    pub unsafe fn post(&self, msg: u32, wparam: WPARAM, lparam: LPARAM) -> io::Result<()> {
+        if PostMessageW(self.hwnd, msg, wparam, lparam) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sends a message to this window with `SendMessageW()` and returns the
+    /// result of the window procedure.
+    ///
+    /// The call is synchronous: The `wndproc` closure runs before this
+    /// function returns. Sending from within the closure re-enters it.
+    ///
+    /// # Safety
+    ///
+    /// `wparam` and `lparam` must be valid for `msg`. Some system messages
+    /// interpret them as pointers, e.g. `lparam` of `WM_SETTEXT` is a null
+    /// terminated UTF-16 string.
+    pub unsafe fn send(&self, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        SendMessageW(self.hwnd, msg, wparam, lparam)
+    }
+
+    /// Shows the window with `ShowWindow(SW_SHOW)`, activating it.
+    ///
+    /// Top-level windows are created hidden unless the `WS_VISIBLE` style was
+    /// set with [`WindowBuilder::style()`].
+    pub fn show(&self) {
+        unsafe { ShowWindow(self.hwnd, SW_SHOW) };
+    }
+
+    /// Hides the window with `ShowWindow(SW_HIDE)`.
+    pub fn hide(&self) {
+        unsafe { ShowWindow(self.hwnd, SW_HIDE) };
+    }
+
+    /// Returns `true` when the window has the `WS_VISIBLE` style.
+    ///
+    /// Like `IsWindowVisible()`, this also requires the parent windows to be
+    /// visible. A visible window can still be covered by other windows.
+    pub fn is_visible(&self) -> bool {
+        unsafe { IsWindowVisible(self.hwnd) != 0 }
+    }
+
+    /// Sets the title of the window with `SetWindowTextW()`.
+    pub fn set_title(&self, title: &str) -> io::Result<()> {
+        if unsafe { SetWindowTextW(self.hwnd, to_wide(title).as_ptr()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     /// Returns a reference to the state shared with the `wndproc` closure.
     pub fn state(&self) -> Pin<&S> {
         unsafe { Pin::new_unchecked(&self.user_data().state) }
     }
 }
 
+impl<S: 'static> Window<RefCell<S>> {
+    /// Same as [`Window::new()`] but wraps the state in a `RefCell` for
+    /// mutable access.
+    ///
+    /// Messages are often dispatched reentrantly: Sending a message, opening
+    /// a modal dialog or calling [`Window::with_state_mut()`] from the
+    /// `wndproc` closure can call the closure again. Borrow the state with
+    /// `try_borrow_mut()` in the closure and handle failures, e.g. by
+    /// returning `None` to fall back to the default window procedure.
+    pub fn new_with_cell<F>(
+        window_type: WindowType,
+        state: S,
+        wndproc: F,
+    ) -> Result<Self, WindowCreationError>
+    where
+        F: Fn(&RefCell<S>, WindowMessage) -> Option<LRESULT> + 'static,
+    {
+        Self::new(window_type, RefCell::new(state), move |state, msg| {
+            wndproc(state.get_ref(), msg)
+        })
+    }
+
+    /// Calls `f` with mutable access to the state.
+    ///
+    /// Returns `None` when the state is borrowed already, i.e. when called
+    /// reentrantly from the `wndproc` closure or from within `f`.
+    pub fn with_state_mut<R>(&self, f: impl FnOnce(&mut S) -> R) -> Option<R> {
+        let mut state = self.state().get_ref().try_borrow_mut().ok()?;
+        Some(f(&mut state))
+    }
+}
+
 unsafe extern "system" fn wndproc_setup(
     hwnd: HWND,
     msg: u32,
@@ -198,28 +690,39 @@ unsafe extern "system" fn wndproc_setup(
     lparam: LPARAM,
 ) -> LRESULT {
     if msg == WM_NCCREATE {
-        let create_params = lparam as *const CREATESTRUCTA;
+        let create_params = lparam as *const CREATESTRUCTW;
         let subclassinfo = &*((*create_params).lpCreateParams as *const SubClassInformation);
 
         // Replace our `wndproc` with the one using the correct type.
-        SetWindowLongPtrA(hwnd, GWLP_WNDPROC, subclassinfo.wndproc as usize as _);
+        // The `W` variant keeps the window a Unicode window.
+        SetWindowLongPtrW(hwnd, GWLP_WNDPROC, subclassinfo.wndproc as usize as _);
 
         // Attach user data to the window so it can be accessed from the
         // `wndproc` callback function when receiving other messages.
         // https://devblogs.microsoft.com/oldnewthing/20191014-00/?p=102992
-        SetWindowLongPtrA(hwnd, GWLP_USERDATA, subclassinfo.user_data as _);
+        match subclassinfo.user_data_slot {
+            UserDataSlot::WindowLongPtr => {
+                SetWindowLongPtrW(hwnd, GWLP_USERDATA, subclassinfo.user_data as _);
+            }
+            UserDataSlot::Property => {
+                SetPropW(hwnd, USER_DATA_PROPERTY, subclassinfo.user_data as _);
+            }
+        }
+        WINDOW_REGISTRY.with_borrow_mut(|registry| {
+            registry.insert(hwnd, (subclassinfo.state_type, subclassinfo.user_data))
+        });
 
         // Forward this message to the freshly registered subclass wndproc.
-        SendMessageA(hwnd, msg, wparam, lparam)
+        SendMessageW(hwnd, msg, wparam, lparam)
     } else {
         // This code path is only reached for messages before `WM_NCCREATE`.
         // On Windows 10/11 `WM_GETMINMAXINFO` is the first and only message
         // before `WM_NCCREATE`.
-        DefWindowProcA(hwnd, msg, wparam, lparam)
+        DefWindowProcW(hwnd, msg, wparam, lparam)
     }
 }
 
-unsafe extern "system" fn wndproc_typed<S, F>(
+unsafe extern "system" fn wndproc_typed<S, F, const PROPERTY: bool>(
     hwnd: HWND,
     msg: u32,
     wparam: WPARAM,
@@ -230,8 +733,10 @@ where
 {
     let user_data_ptr: NonNull<UserData<S, F>> = if mem::size_of::<UserData<S, F>>() == 0 {
         NonNull::dangling()
+    } else if PROPERTY {
+        NonNull::new_unchecked(GetPropW(hwnd, USER_DATA_PROPERTY) as _)
     } else {
-        NonNull::new_unchecked(GetWindowLongPtrA(hwnd, GWLP_USERDATA) as _)
+        NonNull::new_unchecked(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as _)
     };
     let user_data = user_data_ptr.as_ref();
 
@@ -245,6 +750,12 @@ where
         },
     );
 
+    match (msg, ret) {
+        (WM_NCCREATE, Some(0)) => CREATE_REJECTED.set(Some(CreateStage::NcCreateRejected)),
+        (WM_CREATE, Some(-1)) => CREATE_REJECTED.set(Some(CreateStage::CreateRejected)),
+        _ => {}
+    }
+
     if msg == WM_CLOSE {
         // We manage the window lifetime ourselves. Prevent the default
         // handler from calling `DestroyWindow()` to keep the state
@@ -255,11 +766,55 @@ where
     if msg == WM_NCDESTROY {
         // This is the very last message received by this function before
         // the window is destroyed. Deallocate the window user data.
+        // The registry is gone already when destroyed during thread exit.
+        let _ = WINDOW_REGISTRY.try_with(|registry| registry.borrow_mut().remove(&hwnd));
+        if PROPERTY {
+            // Properties must be removed before the window is destroyed.
+            RemovePropW(hwnd, USER_DATA_PROPERTY);
+        }
         drop(Box::from_raw(user_data_ptr.as_ptr()));
         return 0;
     }
 
-    ret.unwrap_or_else(|| DefWindowProcA(hwnd, msg, wparam, lparam))
+    ret.unwrap_or_else(|| DefWindowProcW(hwnd, msg, wparam, lparam))
+}
+
+unsafe fn drop_user_data<S, F>(user_data: *const ()) {
+    drop(Box::from_raw(user_data as *mut UserData<S, F>));
+}
+
+unsafe extern "system" fn subclass_proc<S, F>(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _subclass_id: usize,
+    ref_data: usize,
+) -> LRESULT
+where
+    F: Fn(Pin<&S>, WindowMessage) -> Option<LRESULT> + 'static,
+{
+    let user_data = &*(ref_data as *const UserData<S, F>);
+
+    let ret = (user_data.wndproc)(
+        Pin::new_unchecked(&user_data.state),
+        WindowMessage {
+            hwnd,
+            msg,
+            wparam,
+            lparam,
+        },
+    );
+
+    if msg == WM_NCDESTROY {
+        // The window is destroyed by its owner before our handle was dropped.
+        // Remove the subclass as required, the user data is deallocated when
+        // the handle is dropped. The owner must receive this message, too.
+        RemoveWindowSubclass(hwnd, Some(subclass_proc::<S, F>), SUBCLASS_ID);
+        return DefSubclassProc(hwnd, msg, wparam, lparam);
+    }
+
+    ret.unwrap_or_else(|| DefSubclassProc(hwnd, msg, wparam, lparam))
 }
 
 #[cfg(test)]
@@ -272,6 +827,190 @@ mod test {
         rc::{Rc, Weak},
     };
 
+    #[test]
+    fn send_message() {
+        let w = Window::new(WindowType::MessageOnly, (), |_, msg| {
+            (msg.msg == WM_USER).then_some(msg.wparam as LRESULT + msg.lparam)
+        })
+        .unwrap();
+        assert_eq!(unsafe { w.send(WM_USER, 1, 2) }, 3);
+    }
+
+    #[test]
+    fn builder_custom_class() {
+        let w = WindowBuilder::new(WindowType::TopLevel)
+            .class_name("winmsg-executor-test-class")
+            .title("builder_custom_class")
+            .ex_style(WS_EX_TOOLWINDOW)
+            .build((), |_, _| None)
+            .unwrap();
+
+        let mut class_name = [0; 64];
+        let len = unsafe { GetClassNameW(w.hwnd(), class_name.as_mut_ptr(), 64) };
+        let class_name = String::from_utf16(&class_name[..len as usize]).unwrap();
+        assert_eq!(class_name, "winmsg-executor-test-class");
+        assert_ne!(unsafe { IsWindowUnicode(w.hwnd()) }, 0);
+        let ex_style = unsafe { GetWindowLongPtrW(w.hwnd(), GWL_EXSTYLE) } as u32;
+        assert_ne!(ex_style & WS_EX_TOOLWINDOW, 0);
+        drop(w);
+
+        // Class registration happens only once.
+        let w = WindowBuilder::new(WindowType::TopLevel)
+            .class_name("winmsg-executor-test-class")
+            .rect(10, 20, 300, 200)
+            .build((), |_, _| None)
+            .unwrap();
+        let mut rect = unsafe { mem::zeroed() };
+        unsafe { GetWindowRect(w.hwnd(), &mut rect) };
+        assert_eq!((rect.left, rect.top), (10, 20));
+        assert_eq!((rect.right - rect.left, rect.bottom - rect.top), (300, 200));
+
+        WindowBuilder::new(WindowType::MessageOnly)
+            .class_name("winmsg-executor-test-class")
+            .build((), |_, _| None)
+            .unwrap();
+    }
+
+    #[test]
+    fn lookup_by_hwnd() {
+        let w = Window::new(WindowType::MessageOnly, Cell::new(42), |_, _| None).unwrap();
+        let w_ref = Window::<Cell<i32>>::from_hwnd(w.hwnd()).unwrap();
+        // SAFETY: The closure does not destroy the window.
+        assert_eq!(unsafe { w_ref.with_state(|state| state.get()) }, Some(42));
+
+        // Mismatched state type.
+        assert!(Window::<Cell<u32>>::from_hwnd(w.hwnd()).is_none());
+
+        drop(w);
+        assert_eq!(unsafe { w_ref.with_state(|state| state.get()) }, None);
+    }
+
+    #[test]
+    fn attach_to_foreign_window() {
+        let owner = Window::new(WindowType::MessageOnly, (), |_, msg| {
+            (msg.msg == WM_USER).then_some(1)
+        })
+        .unwrap();
+
+        let attached = unsafe {
+            Window::attach(owner.hwnd(), Cell::new(0), |cnt, msg| {
+                cnt.set(cnt.get() + 1);
+                (msg.msg == WM_USER + 1).then_some(2)
+            })
+        }
+        .unwrap();
+        assert_eq!(unsafe { owner.send(WM_USER, 0, 0) }, 1);
+        assert_eq!(unsafe { owner.send(WM_USER + 1, 0, 0) }, 2);
+        assert_eq!(attached.state().get(), 2);
+
+        // Detaching restores the original window procedure.
+        drop(attached);
+        assert_eq!(unsafe { owner.send(WM_USER + 1, 0, 0) }, 0);
+        assert_eq!(unsafe { owner.send(WM_USER, 0, 0) }, 1);
+    }
+
+    #[test]
+    fn user_data_property() {
+        let w = WindowBuilder::new(WindowType::MessageOnly)
+            .user_data_slot(UserDataSlot::Property)
+            .build(Cell::new(0), |cnt, msg| {
+                (msg.msg == WM_USER).then(|| {
+                    cnt.set(cnt.get() + 1);
+                    cnt.get() as LRESULT
+                })
+            })
+            .unwrap();
+        // `GWLP_USERDATA` is free for other code.
+        unsafe { SetWindowLongPtrW(w.hwnd(), GWLP_USERDATA, 42) };
+        assert_eq!(unsafe { w.send(WM_USER, 0, 0) }, 1);
+        assert_eq!(w.state().get(), 1);
+    }
+
+    #[test]
+    fn visibility_and_title() {
+        let w = Window::new(WindowType::TopLevel, (), |_, _| None).unwrap();
+        assert!(!w.is_visible());
+        w.show();
+        assert!(w.is_visible());
+        w.hide();
+        assert!(!w.is_visible());
+
+        w.set_title("Grüße").unwrap();
+        let mut title = [0; 16];
+        let len = unsafe { GetWindowTextW(w.hwnd(), title.as_mut_ptr(), title.len() as _) };
+        assert_eq!(String::from_utf16(&title[..len as usize]).unwrap(), "Grüße");
+    }
+
+    #[test]
+    fn unicode_class_and_title() {
+        let w = WindowBuilder::new(WindowType::TopLevel)
+            .class_name("winmsg-executor-クラス")
+            .title("ウィンドウ")
+            .build((), |_, _| None)
+            .unwrap();
+        let mut class_name = [0; 64];
+        let len = unsafe { GetClassNameW(w.hwnd(), class_name.as_mut_ptr(), 64) };
+        let class_name = String::from_utf16(&class_name[..len as usize]).unwrap();
+        assert_eq!(class_name, "winmsg-executor-クラス");
+        let mut title = [0; 16];
+        let len = unsafe { GetWindowTextW(w.hwnd(), title.as_mut_ptr(), title.len() as _) };
+        assert_eq!(
+            String::from_utf16(&title[..len as usize]).unwrap(),
+            "ウィンドウ"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "windows-interop")]
+    fn windows_crate_hwnd() {
+        let w = Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap();
+        let hwnd = windows::Win32::Foundation::HWND::from(&w);
+        assert_eq!(hwnd.0, w.hwnd());
+        let w_ref = WindowRef::<()>::try_from(hwnd).unwrap();
+        assert_eq!(windows::Win32::Foundation::HWND::from(&w_ref), hwnd);
+        assert!(WindowRef::<u32>::try_from(hwnd).is_err());
+
+        let hinstance = windows::Win32::Foundation::HINSTANCE::from(&w);
+        assert_eq!(hinstance.0, get_instance_handle());
+    }
+
+    #[test]
+    fn creation_rejected() {
+        let reject = |reject_msg, ret| {
+            Window::new(WindowType::MessageOnly, (), move |_, msg| {
+                (msg.msg == reject_msg).then_some(ret)
+            })
+            .unwrap_err()
+            .stage
+        };
+        assert_eq!(reject(WM_NCCREATE, 0), CreateStage::NcCreateRejected);
+        assert_eq!(reject(WM_CREATE, -1), CreateStage::CreateRejected);
+    }
+
+    #[test]
+    fn mutable_state() {
+        let w = Window::new_with_cell(WindowType::MessageOnly, 0, |state, msg| {
+            (msg.msg == WM_USER).then(|| match state.try_borrow_mut() {
+                Ok(mut state) => {
+                    *state += 1;
+                    0
+                }
+                Err(_) => 1,
+            })
+        })
+        .unwrap();
+        assert_eq!(unsafe { w.send(WM_USER, 0, 0) }, 0);
+
+        // The closure cannot borrow while the state is borrowed outside.
+        let reentered = w.with_state_mut(|state| {
+            *state += 1;
+            unsafe { w.send(WM_USER, 0, 0) }
+        });
+        assert_eq!(reentered, Some(1));
+        assert_eq!(w.with_state_mut(|_| w.with_state_mut(|_| ())), Some(None));
+        assert_eq!(w.with_state_mut(|state| *state), Some(2));
+    }
+
     #[test]
     fn create_destroy_messages() {
         let mut expected_messages = [WM_NCCREATE, WM_CREATE, WM_DESTROY, WM_NCDESTROY].into_iter();
@@ -311,7 +1050,7 @@ mod test {
                     // here we would get the second mutable alias
                     if w.state().try_borrow_mut().is_err() {
                         *state = true;
-                        unsafe { PostMessageA(w.hwnd(), WM_USER, 0, 0) };
+                        unsafe { w.post(WM_USER, 0, 0).unwrap() };
                     }
                 }
                 None
@@ -320,7 +1059,7 @@ mod test {
         });
 
         // Emulate a message from a user clicking on the window somewhere.
-        unsafe { PostMessageA(w.hwnd(), WM_USER, 0, 0) };
+        unsafe { w.post(WM_USER, 0, 0).unwrap() };
         MessageLoop::run(|msg_loop, _| {
             if *w.state().borrow() {
                 msg_loop.quit();
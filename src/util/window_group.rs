@@ -0,0 +1,190 @@
+use std::{
+    any::Any,
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use windows_sys::Win32::Foundation::HWND;
+
+use super::{MessageStream, Window, WindowMessage};
+
+struct Member {
+    // Declared first to unsubscribe before the window is dropped.
+    stream: MessageStream,
+    hwnd: HWND,
+    window: Box<dyn Any>,
+}
+
+/// Owns multiple windows and combines their messages into one stream.
+///
+/// Meant for applications with several windows served by one task: Messages
+/// of all windows, selected by a filter per window, are received from the
+/// group in the order they were dispatched per window. The `hwnd` of a
+/// [`WindowMessage`] tells which window it is for. Windows of different state
+/// types can be added, [`WindowGroup::get()`] looks them up by handle.
+///
+/// All windows are destroyed together when the group is dropped.
+///
+/// ```no_run
+/// use windows_sys::Win32::UI::WindowsAndMessaging::WM_CLOSE;
+/// use winmsg_executor::util::{Window, WindowGroup, WindowType};
+///
+/// let mut group = WindowGroup::new();
+/// for _ in 0..2 {
+///     let window = Window::new(WindowType::TopLevel, (), |_, _| None).unwrap();
+///     group.add(window, |msg| msg == WM_CLOSE);
+/// }
+/// winmsg_executor::block_on(async {
+///     while let Some(msg) = group.recv().await {
+///         group.remove(msg.hwnd);
+///     }
+/// });
+/// ```
+#[derive(Default)]
+pub struct WindowGroup {
+    members: Vec<Member>,
+    // Index of the window polled first, rotated for fairness.
+    next: usize,
+}
+
+impl WindowGroup {
+    /// Creates an empty group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a window to the group and returns its handle.
+    ///
+    /// Messages with an id for which `filter` returns `true` are buffered like
+    /// for [`Window::messages()`] until received from the group.
+    pub fn add<S: 'static>(
+        &mut self,
+        window: Window<S>,
+        filter: impl Fn(u32) -> bool + 'static,
+    ) -> HWND {
+        let hwnd = window.hwnd();
+        self.members.push(Member {
+            stream: window.messages(filter),
+            hwnd,
+            window: Box::new(window),
+        });
+        hwnd
+    }
+
+    /// Removes a window from the group and destroys it.
+    ///
+    /// Buffered messages of the window are discarded. Returns `false` when the
+    /// window is not part of the group.
+    pub fn remove(&mut self, hwnd: HWND) -> bool {
+        let Some(i) = self.members.iter().position(|m| m.hwnd == hwnd) else {
+            return false;
+        };
+        self.members.remove(i);
+        true
+    }
+
+    /// Returns the window with the handle `hwnd`.
+    ///
+    /// Returns `None` when the window is not part of the group or when its
+    /// state is not of type `S`.
+    pub fn get<S: 'static>(&self, hwnd: HWND) -> Option<&Window<S>> {
+        let member = self.members.iter().find(|m| m.hwnd == hwnd)?;
+        member.window.downcast_ref()
+    }
+
+    /// Returns the number of windows in the group.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns `true` when the group has no windows.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Receives the next message of any window, `None` when the group is
+    /// empty.
+    ///
+    /// Windows destroyed by other means are removed from the group once all
+    /// their buffered messages were received.
+    pub async fn recv(&mut self) -> Option<WindowMessage> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for WindowGroup {
+    type Item = WindowMessage;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WindowMessage>> {
+        let this = self.get_mut();
+        let mut i = 0;
+        while i < this.members.len() {
+            let index = (this.next + i) % this.members.len();
+            match Pin::new(&mut this.members[index].stream).poll_next(cx) {
+                Poll::Ready(Some(msg)) => {
+                    this.next = index + 1;
+                    return Poll::Ready(Some(msg));
+                }
+                Poll::Ready(None) => {
+                    this.members.remove(index);
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        if this.members.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use windows_sys::Win32::UI::WindowsAndMessaging::*;
+
+    use super::*;
+    use crate::{block_on, util::WindowType};
+
+    #[test]
+    fn combined_messages() {
+        let mut group = WindowGroup::new();
+        let a = group.add(
+            Window::new(WindowType::MessageOnly, Cell::new(1), |_, _| None).unwrap(),
+            |msg| msg == WM_USER,
+        );
+        let b = group.add(
+            Window::new(WindowType::MessageOnly, (), |_, _| None).unwrap(),
+            |msg| msg == WM_USER,
+        );
+        assert_eq!(group.get::<Cell<i32>>(a).unwrap().state().get(), 1);
+        assert!(group.get::<()>(a).is_none());
+
+        for hwnd in [a, b] {
+            unsafe { PostMessageW(hwnd, WM_USER, 0, 0) };
+        }
+        block_on(async {
+            let mut hwnds = vec![
+                group.recv().await.unwrap().hwnd,
+                group.recv().await.unwrap().hwnd,
+            ];
+            hwnds.sort();
+            let mut expected = vec![a, b];
+            expected.sort();
+            assert_eq!(hwnds, expected);
+        });
+
+        assert!(group.remove(a));
+        assert!(!group.remove(a));
+        assert_eq!(unsafe { IsWindow(a) }, 0);
+
+        // Ends once the last window is destroyed.
+        unsafe { DestroyWindow(b) };
+        assert!(block_on(group.recv()).is_none());
+        assert!(group.is_empty());
+    }
+}